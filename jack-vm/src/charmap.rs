@@ -2,15 +2,30 @@ use std::collections::HashMap;
 
 use crate::memory::WordSize;
 
+const CHAR_HEIGHT: usize = 11;
+const CHAR_WIDTH: usize = 8;
+// BDF bitmap rows are read into a u32, so glyphs wider than this are rejected
+const MAX_BDF_WIDTH: i32 = 32;
+
+// Unicode characters that BDF fonts use for the Jack special keys
+const BDF_SPECIAL_KEYS: [(u32, WordSize); 6] = [
+    (0x21B5, 128), // ↵ newline
+    (0x232B, 129), // ⌫ backspace
+    (0x2190, 130), // ← left arrow
+    (0x2191, 131), // ↑ up arrow
+    (0x2192, 132), // → right arrow
+    (0x2193, 133), // ↓ down arrow
+];
+
 struct Char {
-    _glyph: char,
+    glyph: char,
     bitmap: Vec<u8>
 }
 
 impl Char {
-    fn new(_glyph: char, bitmap: Vec<u8>) -> Char {
+    fn new(glyph: char, bitmap: Vec<u8>) -> Char {
         Char {
-            _glyph,
+            glyph,
             bitmap,
         }
     }
@@ -131,7 +146,373 @@ impl CharMap {
         if self.cmap.contains_key(character) {
             self.cmap.get(character).unwrap().bitmap.as_ref()
         } else {
-            self.cmap.get(&(0 as i16)).unwrap().bitmap.as_ref()
+            self.cmap.get(&0).unwrap().bitmap.as_ref()
+        }
+    }
+
+    /**
+     * Returns the character a code is drawn as, the block for codes without a glyph
+     */
+    pub fn glyph(&self, character: WordSize) -> char {
+        self.cmap.get(&character).unwrap_or(&self.cmap[&0]).glyph
+    }
+
+    /**
+     * Loads glyphs from the text font format, replacing any glyphs already defined for their codes.
+     * Each glyph starts with a `char <code>` line, where code is a number or a single character,
+     * followed by up to 11 rows of 8 pixels drawn with '#' for on and '.' for off.
+     * Missing rows are blank, blank lines and lines starting with '//' are ignored.
+     *
+     * char 130
+     * ...#....
+     * ..##....
+     * .#######
+     *
+     * Returns the number of glyphs loaded
+     */
+    pub fn load_text(&mut self, source: &str) -> Result<usize, String> {
+        let mut glyphs: Vec<(WordSize, Char)> = Vec::new();
+        for (line_num, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            if let Some(code) = line.strip_prefix("char ") {
+                let code = parse_char_code(code.trim()).ok_or(format!(
+                    "line {}: invalid character code '{}'",
+                    line_num + 1,
+                    code
+                ))?;
+                glyphs.push((code, Char::new(glyph_for(code), Vec::new())));
+                continue;
+            }
+            let bitmap = match glyphs.last_mut() {
+                Some((_, glyph)) => &mut glyph.bitmap,
+                None => {
+                    return Err(format!(
+                        "line {}: pixel row before the first 'char' line",
+                        line_num + 1
+                    ))
+                }
+            };
+            if bitmap.len() == CHAR_HEIGHT {
+                return Err(format!(
+                    "line {}: glyphs have at most {} rows",
+                    line_num + 1,
+                    CHAR_HEIGHT
+                ));
+            }
+            if line.chars().count() != CHAR_WIDTH {
+                return Err(format!(
+                    "line {}: rows must be {} pixels wide",
+                    line_num + 1,
+                    CHAR_WIDTH
+                ));
+            }
+            let mut row = 0;
+            for (col, pixel) in line.chars().enumerate() {
+                match pixel {
+                    '#' => row |= 1 << col,
+                    '.' => (),
+                    _ => {
+                        return Err(format!(
+                            "line {}: unexpected pixel '{}'",
+                            line_num + 1,
+                            pixel
+                        ))
+                    }
+                }
+            }
+            bitmap.push(row);
+        }
+        Ok(self.insert_all(glyphs))
+    }
+
+    /**
+     * Loads glyphs from a BDF font, replacing any glyphs already defined for their codes.
+     * Glyphs are placed on their baseline in the 8x11 character cell and clipped to it.
+     * Unicode arrows, ↵ and ⌫ are loaded as the Jack key codes 128-133.
+     * Returns the number of glyphs loaded
+     */
+    pub fn load_bdf(&mut self, source: &str) -> Result<usize, String> {
+        let mut ascent: Option<i32> = None;
+        let mut font_x_offset = 0;
+        let mut glyphs: Vec<(WordSize, Char)> = Vec::new();
+        // encoding, bbx (width, height, x offset, y offset), bitmap rows
+        let mut encoding: Option<u32> = None;
+        let mut bbx = (0, 0, 0, 0);
+        let mut rows: Option<Vec<u32>> = None;
+
+        for (line_num, line) in source.lines().enumerate() {
+            let error = |message: &str| format!("line {}: {}", line_num + 1, message);
+            let mut words = line.split_whitespace();
+            let keyword = match words.next() {
+                Some(keyword) => keyword,
+                None => continue,
+            };
+            let numbers: Vec<i32> = words.filter_map(|word| word.parse().ok()).collect();
+            if let Some(bitmap_rows) = rows.as_mut() {
+                if keyword == "ENDCHAR" {
+                    if let Some(code) = encoding.and_then(bdf_char_code) {
+                        let bitmap =
+                            place_bdf_glyph(bitmap_rows, bbx, ascent.unwrap_or(0), font_x_offset);
+                        glyphs.push((code, Char::new(glyph_for(code), bitmap)));
+                    }
+                    rows = None;
+                } else {
+                    let row = u32::from_str_radix(keyword, 16)
+                        .map_err(|_| error("invalid bitmap row"))?;
+                    bitmap_rows.push(row);
+                }
+                continue;
+            }
+            match (keyword, numbers.as_slice()) {
+                ("FONTBOUNDINGBOX", [_, height, x_offset, y_offset]) => {
+                    ascent.get_or_insert(height + y_offset);
+                    font_x_offset = *x_offset;
+                }
+                ("FONT_ASCENT", [font_ascent]) => ascent = Some(*font_ascent),
+                ("STARTCHAR", _) => {
+                    encoding = None;
+                    bbx = (0, 0, 0, 0);
+                }
+                // a negative encoding means the glyph has no code point
+                ("ENCODING", [code, ..]) => encoding = u32::try_from(*code).ok(),
+                ("BBX", [width, ..]) if !(0..=MAX_BDF_WIDTH).contains(width) => {
+                    return Err(error(&format!(
+                        "glyph widths must be 0 to {} pixels",
+                        MAX_BDF_WIDTH
+                    )))
+                }
+                ("BBX", [width, height, x_offset, y_offset]) => {
+                    bbx = (*width, *height, *x_offset, *y_offset)
+                }
+                ("BBX", _) => return Err(error("BBX needs width, height, x offset and y offset")),
+                ("BITMAP", _) => rows = Some(Vec::new()),
+                _ => (),
+            }
+        }
+        if rows.is_some() {
+            return Err("BDF font ends inside a glyph bitmap".to_string());
+        }
+        Ok(self.insert_all(glyphs))
+    }
+
+    fn insert_all(&mut self, glyphs: Vec<(WordSize, Char)>) -> usize {
+        let count = glyphs.len();
+        for (code, mut glyph) in glyphs {
+            glyph.bitmap.resize(CHAR_HEIGHT, 0);
+            self.cmap.insert(code, glyph);
         }
+        count
+    }
+}
+
+/**
+ * Reads a character code written as a number or as the character itself
+ */
+fn parse_char_code(code: &str) -> Option<WordSize> {
+    if let Ok(number) = code.parse::<WordSize>() {
+        return Some(number);
+    }
+    let mut chars = code.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => WordSize::try_from(c as u32).ok(),
+        _ => None,
+    }
+}
+
+/**
+ * Maps a BDF encoding to the Jack character code it is drawn for
+ */
+fn bdf_char_code(encoding: u32) -> Option<WordSize> {
+    match BDF_SPECIAL_KEYS
+        .iter()
+        .find(|(unicode, _)| *unicode == encoding)
+    {
+        Some((_, code)) => Some(*code),
+        None => WordSize::try_from(encoding).ok(),
+    }
+}
+
+fn glyph_for(code: WordSize) -> char {
+    match BDF_SPECIAL_KEYS.iter().find(|(_, key)| *key == code) {
+        Some((unicode, _)) => char::from_u32(*unicode).unwrap(),
+        None => char::from_u32(code as u32).unwrap_or('█'),
+    }
+}
+
+/**
+ * Positions a BDF glyph bitmap in the character cell
+ * BDF rows are stored most significant bit first, so the bits are reversed
+ * to put the leftmost pixel in the least significant bit like display memory
+ */
+fn place_bdf_glyph(
+    rows: &[u32],
+    bbx: (i32, i32, i32, i32),
+    ascent: i32,
+    font_x_offset: i32,
+) -> Vec<u8> {
+    let (width, height, x_offset, y_offset) = bbx;
+    let row_bits = (width + 7) / 8 * 8;
+    let first_row = ascent - (y_offset + height);
+    let mut bitmap = vec![0; CHAR_HEIGHT];
+    for (r, row) in rows.iter().enumerate() {
+        let cell_row = first_row + r as i32;
+        if !(0..CHAR_HEIGHT as i32).contains(&cell_row) {
+            continue;
+        }
+        for pixel in 0..width {
+            let col = x_offset - font_x_offset + pixel;
+            if (0..CHAR_WIDTH as i32).contains(&col) && (row >> (row_bits - 1 - pixel)) & 1 == 1 {
+                bitmap[cell_row as usize] |= 1 << col;
+            }
+        }
+    }
+    bitmap
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FONT_HEADER: &str = "STARTFONT 2.1\n\
+        FONTBOUNDINGBOX 8 11 0 -2\n\
+        FONT_ASCENT 9\n";
+
+    fn bdf_glyph(encoding: u32, bbx: &str, rows: &[&str]) -> String {
+        format!(
+            "STARTCHAR glyph\nENCODING {}\nBBX {}\nBITMAP\n{}\nENDCHAR\n",
+            encoding,
+            bbx,
+            rows.join("\n")
+        )
+    }
+
+    #[test]
+    fn load_text_reads_rows_leftmost_pixel_first() {
+        let mut charmap = CharMap::new();
+        let source = "// a test glyph\nchar A\n#.......\n\n.......#\nchar 200\n########\n";
+        assert_eq!(charmap.load_text(source), Ok(2));
+        assert_eq!(
+            charmap.get_bitmap(&65),
+            &vec![1, 128, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(charmap.get_bitmap(&200)[0], 255);
+        assert_eq!(charmap.glyph(65), 'A');
+    }
+
+    #[test]
+    fn load_text_rejects_malformed_glyphs() {
+        let mut charmap = CharMap::new();
+        let original = charmap.get_bitmap(&65).clone();
+        let too_tall = format!("char A\n{}", "........\n".repeat(CHAR_HEIGHT + 1));
+        for (source, message) in [
+            ("#.......", "line 1: pixel row before the first 'char' line"),
+            ("char AB", "line 1: invalid character code 'AB'"),
+            ("char A\n#......", "line 2: rows must be 8 pixels wide"),
+            ("char A\n#......x", "line 2: unexpected pixel 'x'"),
+            (too_tall.as_str(), "line 13: glyphs have at most 11 rows"),
+        ] {
+            assert_eq!(charmap.load_text(source), Err(message.to_string()));
+        }
+        // nothing is loaded from a source with an error
+        assert_eq!(charmap.get_bitmap(&65), &original);
+    }
+
+    #[test]
+    fn load_bdf_places_glyphs_on_the_baseline() {
+        let mut charmap = CharMap::new();
+        let source = format!(
+            "{}{}ENDFONT\n",
+            FONT_HEADER,
+            bdf_glyph(65, "8 2 0 0", &["80", "01"])
+        );
+        assert_eq!(charmap.load_bdf(&source), Ok(1));
+        // the glyph sits on the baseline, 9 rows down, with its leftmost pixel in bit 0
+        assert_eq!(
+            charmap.get_bitmap(&65),
+            &vec![0, 0, 0, 0, 0, 0, 0, 1, 128, 0, 0]
+        );
+    }
+
+    #[test]
+    fn load_bdf_clips_glyphs_to_the_cell() {
+        let mut charmap = CharMap::new();
+        // 16 pixels wide, shifted 4 pixels left and hanging below the cell
+        let source = format!(
+            "{}{}",
+            FONT_HEADER,
+            bdf_glyph(66, "16 3 -4 -3", &["FFFF", "0F00", "00F0"])
+        );
+        assert_eq!(charmap.load_bdf(&source), Ok(1));
+        assert_eq!(
+            charmap.get_bitmap(&66),
+            &vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 15]
+        );
+    }
+
+    #[test]
+    fn load_bdf_maps_arrows_and_editing_keys_to_jack_codes() {
+        let mut charmap = CharMap::new();
+        let glyphs: String = ['↵', '⌫', '←', '↑', '→', '↓']
+            .iter()
+            .map(|&unicode| bdf_glyph(unicode as u32, "8 1 0 0", &["FF"]))
+            .collect();
+        let source = format!("{}{}", FONT_HEADER, glyphs);
+        assert_eq!(charmap.load_bdf(&source), Ok(6));
+        for (code, unicode) in (128..=133).zip(['↵', '⌫', '←', '↑', '→', '↓']) {
+            assert_eq!(charmap.glyph(code), unicode);
+            assert_eq!(charmap.get_bitmap(&code)[8], 255);
+        }
+    }
+
+    #[test]
+    fn load_bdf_rejects_glyphs_wider_than_a_row() {
+        let mut charmap = CharMap::new();
+        let original = charmap.get_bitmap(&67).clone();
+        let source = format!(
+            "{}{}",
+            FONT_HEADER,
+            bdf_glyph(67, "40 1 0 0", &["FFFFFFFFFF"])
+        );
+        assert_eq!(
+            charmap.load_bdf(&source),
+            Err("line 6: glyph widths must be 0 to 32 pixels".to_string())
+        );
+        assert_eq!(charmap.get_bitmap(&67), &original);
+    }
+
+    #[test]
+    fn load_bdf_reads_glyphs_32_pixels_wide() {
+        let mut charmap = CharMap::new();
+        let source = format!(
+            "{}{}",
+            FONT_HEADER,
+            bdf_glyph(68, "32 1 0 0", &["A5000000"])
+        );
+        assert_eq!(charmap.load_bdf(&source), Ok(1));
+        assert_eq!(charmap.get_bitmap(&68)[8], 0b1010_0101);
+    }
+
+    #[test]
+    fn load_bdf_rejects_unfinished_bitmaps() {
+        let mut charmap = CharMap::new();
+        let source = format!(
+            "{}STARTCHAR A\nENCODING 65\nBBX 8 1 0 0\nBITMAP\nZZ\n",
+            FONT_HEADER
+        );
+        assert_eq!(
+            charmap.load_bdf(&source),
+            Err("line 8: invalid bitmap row".to_string())
+        );
+        let source = format!(
+            "{}STARTCHAR A\nENCODING 65\nBBX 8 1 0 0\nBITMAP\nFF\n",
+            FONT_HEADER
+        );
+        assert_eq!(
+            charmap.load_bdf(&source),
+            Err("BDF font ends inside a glyph bitmap".to_string())
+        );
     }
 }
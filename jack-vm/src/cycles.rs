@@ -0,0 +1,87 @@
+/* Hack CPU cycle estimates for VM commands and native functions
+ * Every Hack instruction takes one cycle, so a command costs as many cycles as the instructions
+ * its standard nand2tetris translation executes. Native functions are charged roughly what the
+ * reference Jack OS implementation of the function costs for typical arguments.
+ * */
+
+use crate::parser::{Command, Segment};
+
+// call f n: push return address, LCL, ARG, THIS and THAT, reposition ARG and LCL, goto f
+const CALL_CYCLES: u64 = 49;
+// return: restore the caller's frame, move the return value and jump back
+const RETURN_CYCLES: u64 = 42;
+// function f k: each local is initialized with a push constant 0
+const PUSH_CONSTANT_CYCLES: u64 = 7;
+
+pub fn command_cycles(command: &Command) -> u64 {
+    match command {
+        Command::Push(segment, _) => match segment {
+            // the address is computed from a segment pointer
+            Segment::Local | Segment::Argument | Segment::This | Segment::That => 10,
+            Segment::Constant | Segment::Static | Segment::Temp | Segment::Pointer => {
+                PUSH_CONSTANT_CYCLES
+            }
+        },
+        Command::Pop(segment, _) => match segment {
+            // the address is stashed in R13 while the value is popped
+            Segment::Local | Segment::Argument | Segment::This | Segment::That => 12,
+            _ => 5,
+        },
+        Command::Add | Command::Sub | Command::And | Command::Or => 5,
+        Command::Neg | Command::Not => 3,
+        Command::Eq | Command::Gt | Command::Lt => 13,
+        Command::Label(_) => 0,
+        Command::GoTo(_) => 2,
+        Command::IfGoTo(_) => 5,
+        Command::Function(_, num_vars) => PUSH_CONSTANT_CYCLES * (*num_vars).max(0) as u64,
+        Command::Call(_, _) => CALL_CYCLES,
+        Command::Return => RETURN_CYCLES,
+    }
+}
+
+/**
+ * Estimated cycles a native function takes, including its own return.
+ * The call itself is charged by the Call command.
+ */
+pub fn native_cycles(name: &str) -> u64 {
+    let body = match name {
+        "Math.multiply" => 600,
+        "Math.divide" => 900,
+        "Math.sqrt" => 500,
+        "Math.pow" | "Mod.mod" => 700,
+        "Math.min" | "Math.max" => 30,
+        "Math.abs" => 25,
+        "String.new" | "Array.new" | "Memory.alloc" => 150,
+        "String.dispose" | "Array.dispose" | "Memory.deAlloc" => 80,
+        "String.length" => 20,
+        "String.charAt" => 30,
+        "String.setCharAt" => 35,
+        "String.appendChar" => 50,
+        "String.eraseLastChar" => 30,
+        "String.intValue" => 300,
+        "String.setInt" => 400,
+        "String.backSpace" | "String.doubleQuote" | "String.newLine" => 10,
+        "Output.printChar" | "Output.backSpace" => 420,
+        "Output.moveCursor" => 450,
+        "Output.printString" => 2500,
+        "Output.printInt" => 1500,
+        "Output.println" => 40,
+        "Screen.setColor" => 10,
+        "Screen.drawPixel" => 300,
+        "Screen.clearScreen" | "Screen.fillScreen" => 65000,
+        "Screen.drawLine" => 5000,
+        "Screen.drawRectangle" | "Screen.drawRectangleOutline" => 20000,
+        "Screen.drawCircle" => 30000,
+        "Keyboard.keyPressed" | "Memory.peek" | "Memory.poke" => 15,
+        "Keyboard.isDown" | "Keyboard.wasPressed" | "Keyboard.wasReleased" => 20,
+        "Mouse.x" | "Mouse.y" | "Mouse.buttons" | "Mouse.clicked" => 15,
+        "Gamepad.buttons" => 15,
+        "Gamepad.isDown" | "Gamepad.wasPressed" => 20,
+        "Sound.tone" | "Sound.noise" | "Sound.stop" => 20,
+        "Storage.write" | "Storage.read" => 500,
+        "Storage.delete" => 100,
+        "Sys.halt" => 5,
+        _ => 50,
+    };
+    body + RETURN_CYCLES
+}
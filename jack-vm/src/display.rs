@@ -0,0 +1,227 @@
+use js_sys::Function;
+use wasm_bindgen::{Clamped, JsCast, JsValue};
+use web_sys::{
+    CanvasRenderingContext2d, HtmlCanvasElement, ImageData, OffscreenCanvas,
+    OffscreenCanvasRenderingContext2d,
+};
+
+use crate::memory::{WordSize, DISPLAY_HEIGHT, DISPLAY_WIDTH, WORDSIZE};
+
+/**
+ * Rectangle of the display that changed since the last render, in pixels
+ * left and top are inclusive, right and bottom are exclusive
+ */
+#[derive(Clone, Copy)]
+pub(crate) struct DirtyRegion {
+    pub left: WordSize,
+    pub top: WordSize,
+    pub right: WordSize,
+    pub bottom: WordSize,
+}
+
+impl DirtyRegion {
+    pub fn full_display() -> DirtyRegion {
+        DirtyRegion {
+            left: 0,
+            top: 0,
+            right: DISPLAY_WIDTH,
+            bottom: DISPLAY_HEIGHT,
+        }
+    }
+
+    pub fn union(self, other: DirtyRegion) -> DirtyRegion {
+        DirtyRegion {
+            left: WordSize::min(self.left, other.left),
+            top: WordSize::min(self.top, other.top),
+            right: WordSize::max(self.right, other.right),
+            bottom: WordSize::max(self.bottom, other.bottom),
+        }
+    }
+
+    fn width(&self) -> WordSize {
+        self.right - self.left
+    }
+
+    fn height(&self) -> WordSize {
+        self.bottom - self.top
+    }
+}
+
+/**
+ * RGBA colors used to draw pixels that are on (fill) and off (empty)
+ */
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub fill: [u8; 4],
+    pub empty: [u8; 4],
+}
+
+impl Palette {
+    /**
+     * Green phosphor on a dark background, the default look
+     */
+    pub const GREEN: Palette = Palette {
+        fill: [0, 255, 0, 255],
+        empty: [10, 10, 10, 255],
+    };
+
+    /**
+     * Black pixels on white, like the nand2tetris CPU emulator
+     */
+    pub const CLASSIC: Palette = Palette {
+        fill: [0, 0, 0, 255],
+        empty: [255, 255, 255, 255],
+    };
+
+    pub const AMBER: Palette = Palette {
+        fill: [255, 176, 0, 255],
+        empty: [20, 12, 0, 255],
+    };
+
+    pub const HIGH_CONTRAST: Palette = Palette {
+        fill: [255, 255, 255, 255],
+        empty: [0, 0, 0, 255],
+    };
+
+    pub fn from_name(name: &str) -> Option<Palette> {
+        match name {
+            "green" => Some(Palette::GREEN),
+            "classic" => Some(Palette::CLASSIC),
+            "amber" => Some(Palette::AMBER),
+            "high-contrast" => Some(Palette::HIGH_CONTRAST),
+            _ => None,
+        }
+    }
+
+    /**
+     * Builds a palette from two colors packed as 0xRRGGBBAA
+     */
+    pub fn from_rgba(fill: u32, empty: u32) -> Palette {
+        Palette {
+            fill: fill.to_be_bytes(),
+            empty: empty.to_be_bytes(),
+        }
+    }
+
+    /**
+     * Converts a display word into 16 RGBA pixels
+     * Pixel (r, c) is the c%16 bit of the word, so the least significant bit is leftmost
+     */
+    pub fn word_pixels(&self, word: WordSize) -> Vec<u8> {
+        (0..WORDSIZE)
+            .flat_map(|bit| {
+                if (word >> bit) & 1 == 1 {
+                    self.fill
+                } else {
+                    self.empty
+                }
+            })
+            .collect()
+    }
+}
+
+/**
+ * Where rendered frames of the display go
+ * Canvas: an on-page <canvas> element
+ * OffscreenCanvas: a canvas transferred to a Web Worker
+ * Callback: a javascript function called as callback(imageData, x, y, width, height),
+ * where imageData holds the whole display and x, y, width, height is the changed region
+ * Headless: nothing is drawn, the framebuffer can still be read through its pointer
+ */
+pub enum DisplayTarget {
+    Canvas(CanvasRenderingContext2d),
+    OffscreenCanvas(OffscreenCanvasRenderingContext2d),
+    Callback(Function),
+    Headless,
+}
+
+impl DisplayTarget {
+    /**
+     * Builds a display target from whatever javascript passed to the Program constructor:
+     * a canvas, an OffscreenCanvas, one of their 2d contexts, a function, or undefined/null
+     */
+    pub fn from_js(target: JsValue) -> DisplayTarget {
+        if target.is_undefined() || target.is_null() {
+            DisplayTarget::Headless
+        } else if let Some(canvas) = target.dyn_ref::<OffscreenCanvas>() {
+            canvas.set_width(DISPLAY_WIDTH as u32);
+            canvas.set_height(DISPLAY_HEIGHT as u32);
+            let context = canvas
+                .get_context("2d")
+                .unwrap()
+                .unwrap()
+                .dyn_into::<OffscreenCanvasRenderingContext2d>()
+                .unwrap();
+            DisplayTarget::OffscreenCanvas(context)
+        } else if let Some(canvas) = target.dyn_ref::<HtmlCanvasElement>() {
+            canvas.set_width(DISPLAY_WIDTH as u32);
+            canvas.set_height(DISPLAY_HEIGHT as u32);
+            let context = canvas
+                .get_context("2d")
+                .unwrap()
+                .unwrap()
+                .dyn_into::<CanvasRenderingContext2d>()
+                .unwrap();
+            DisplayTarget::Canvas(context)
+        } else if target.is_instance_of::<OffscreenCanvasRenderingContext2d>() {
+            DisplayTarget::OffscreenCanvas(target.unchecked_into())
+        } else if target.is_instance_of::<CanvasRenderingContext2d>() {
+            DisplayTarget::Canvas(target.unchecked_into())
+        } else if target.is_function() {
+            DisplayTarget::Callback(target.unchecked_into())
+        } else {
+            panic!(
+                "Display target must be a canvas, an OffscreenCanvas, a 2d context or a function"
+            )
+        }
+    }
+
+    /**
+     * Draws the region of the framebuffer onto the target with a single blit
+     */
+    pub(crate) fn blit(&self, framebuffer: &[u8], region: DirtyRegion) {
+        if let DisplayTarget::Headless = self {
+            return;
+        }
+        let image = ImageData::new_with_u8_clamped_array_and_sh(
+            Clamped(framebuffer),
+            DISPLAY_WIDTH as u32,
+            DISPLAY_HEIGHT as u32,
+        )
+        .expect("Error creating ImageData");
+        let result = match self {
+            DisplayTarget::Canvas(context) => context
+                .put_image_data_with_dirty_x_and_dirty_y_and_dirty_width_and_dirty_height(
+                    &image,
+                    0.into(),
+                    0.into(),
+                    region.left.into(),
+                    region.top.into(),
+                    region.width().into(),
+                    region.height().into(),
+                ),
+            DisplayTarget::OffscreenCanvas(context) => context
+                .put_image_data_with_dirty_x_and_dirty_y_and_dirty_width_and_dirty_height(
+                    &image,
+                    0.into(),
+                    0.into(),
+                    region.left.into(),
+                    region.top.into(),
+                    region.width().into(),
+                    region.height().into(),
+                ),
+            DisplayTarget::Callback(callback) => {
+                let args = js_sys::Array::of5(
+                    &image,
+                    &region.left.into(),
+                    &region.top.into(),
+                    &region.width().into(),
+                    &region.height().into(),
+                );
+                callback.apply(&JsValue::NULL, &args).map(|_| ())
+            }
+            DisplayTarget::Headless => Ok(()),
+        };
+        result.expect("Error when drawing display");
+    }
+}
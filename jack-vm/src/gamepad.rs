@@ -0,0 +1,49 @@
+use crate::memory::WordSize;
+
+/*
+ * Gamepad button flags, as passed to Program::set_gamepad and returned by Gamepad.buttons
+ * up: 1, down: 2, left: 4, right: 8
+ * A: 16, B: 32, X: 64, Y: 128
+ * start: 256, select: 512
+ */
+const ALL_BUTTONS: WordSize = 1023;
+
+/**
+ * A controller with a d-pad, four face buttons, start and select, as reported by the host
+ * The host decides where the buttons come from, e.g. a browser Gamepad or some keyboard keys
+ */
+pub struct Gamepad {
+    buttons: WordSize,
+    pressed: WordSize,
+}
+
+impl Gamepad {
+    pub fn new() -> Gamepad {
+        Gamepad {
+            buttons: 0,
+            pressed: 0,
+        }
+    }
+
+    /**
+     * Sets the buttons held down, latching every button that wasn't down before as pressed
+     */
+    pub fn set_buttons(&mut self, buttons: WordSize) {
+        let buttons = buttons & ALL_BUTTONS;
+        self.pressed |= buttons & !self.buttons;
+        self.buttons = buttons;
+    }
+
+    pub fn buttons(&self) -> WordSize {
+        self.buttons
+    }
+
+    /**
+     * Returns which of the buttons were pressed since they were last asked about, clearing them
+     */
+    pub fn take_pressed(&mut self, buttons: WordSize) -> WordSize {
+        let pressed = self.pressed & buttons;
+        self.pressed &= !buttons;
+        pressed
+    }
+}
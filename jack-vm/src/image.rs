@@ -0,0 +1,713 @@
+/* This module encodes snapshots of display memory as image files
+ * Some things to note:
+ *  1. images are built straight from display memory, never read back from a canvas
+ *  2. PBM is the netpbm binary bitmap (P4), where 1 is black
+ *  3. PNG is written as a 1 bit image, either grayscale (black on white) or indexed with the
+ *     display palette, compressed with a small LZ77 + fixed huffman deflate encoder
+ *  4. animations are written as APNG (PNG frames in fcTL/fdAT chunks) or as GIF89a with
+ *     LZW compressed frames, both looping forever
+ * */
+
+use std::collections::HashMap;
+
+use crate::display::Palette;
+use crate::memory::{WordSize, DISPLAY_HEIGHT, DISPLAY_WIDTH, WORDSIZE};
+
+const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+// Largest pixel scale for exported images, 8192 x 4096 pixels
+pub(crate) const MAX_SCALE: u32 = 16;
+
+/**
+ * Encodes display memory as a binary PBM (P4) image
+ */
+pub fn encode_pbm(display: &[WordSize]) -> Vec<u8> {
+    let mut pbm = format!("P4\n{} {}\n", DISPLAY_WIDTH, DISPLAY_HEIGHT).into_bytes();
+    // display words hold their leftmost pixel in the least significant bit,
+    // PBM rows hold it in the most significant bit of each byte
+    for word in display {
+        let [low, high] = word.to_le_bytes();
+        pbm.push(low.reverse_bits());
+        pbm.push(high.reverse_bits());
+    }
+    pbm
+}
+
+/**
+ * Encodes display memory as a PNG image, with every pixel scaled to a scale x scale square.
+ * With a palette the image uses its colors, otherwise it is black on white.
+ */
+pub fn encode_png(display: &[WordSize], scale: u32, palette: Option<&Palette>) -> Vec<u8> {
+    let mut png = PNG_SIGNATURE.to_vec();
+    write_header_chunks(&mut png, scale, palette);
+    write_chunk(
+        &mut png,
+        b"IDAT",
+        &zlib_compress(&scanlines(display, scale, palette)),
+    );
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/**
+ * Encodes a sequence of displays as an animated PNG, each frame shown for its delay in ms
+ */
+pub fn encode_apng(
+    frames: &[(&[WordSize], u32)],
+    scale: u32,
+    palette: Option<&Palette>,
+) -> Vec<u8> {
+    let mut png = PNG_SIGNATURE.to_vec();
+    write_header_chunks(&mut png, scale, palette);
+
+    // acTL: number of frames, loop forever
+    let mut animation_control = (frames.len() as u32).to_be_bytes().to_vec();
+    animation_control.extend_from_slice(&0u32.to_be_bytes());
+    write_chunk(&mut png, b"acTL", &animation_control);
+
+    // fcTL and fdAT chunks share one sequence counter
+    let mut sequence = 0u32;
+    for (i, (display, delay_ms)) in frames.iter().enumerate() {
+        let mut frame_control = sequence.to_be_bytes().to_vec();
+        frame_control.extend_from_slice(&(DISPLAY_WIDTH as u32 * scale).to_be_bytes());
+        frame_control.extend_from_slice(&(DISPLAY_HEIGHT as u32 * scale).to_be_bytes());
+        // x and y offset
+        frame_control.extend_from_slice(&[0; 8]);
+        // delay as a fraction of a second: delay_ms / 1000
+        frame_control
+            .extend_from_slice(&(u32::min(*delay_ms, u16::MAX as u32) as u16).to_be_bytes());
+        frame_control.extend_from_slice(&1000u16.to_be_bytes());
+        // dispose op none, blend op source
+        frame_control.extend_from_slice(&[0, 0]);
+        write_chunk(&mut png, b"fcTL", &frame_control);
+        sequence += 1;
+
+        let data = zlib_compress(&scanlines(display, scale, palette));
+        if i == 0 {
+            // the first frame doubles as the still image for viewers without APNG support
+            write_chunk(&mut png, b"IDAT", &data);
+        } else {
+            let mut frame_data = sequence.to_be_bytes().to_vec();
+            frame_data.extend_from_slice(&data);
+            write_chunk(&mut png, b"fdAT", &frame_data);
+            sequence += 1;
+        }
+    }
+
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/**
+ * Encodes a sequence of displays as an animated GIF, each frame shown for its delay in ms.
+ * GIF delays are in hundredths of a second, so shorter delays are rounded up.
+ * Each frame takes a byte per output pixel while it is encoded, so a scale outside of
+ * 1 to MAX_SCALE is an error
+ */
+pub fn encode_gif(
+    frames: &[(&[WordSize], u32)],
+    scale: u32,
+    palette: Option<&Palette>,
+) -> Result<Vec<u8>, String> {
+    if !(1..=MAX_SCALE).contains(&scale) {
+        return Err(format!(
+            "GIF scale must be from 1 to {}, got {}",
+            MAX_SCALE, scale
+        ));
+    }
+    let width = (DISPLAY_WIDTH as u32 * scale) as u16;
+    let height = (DISPLAY_HEIGHT as u32 * scale) as u16;
+    let mut gif = b"GIF89a".to_vec();
+
+    // logical screen with a global color table of 2 colors: 0 is empty, 1 is filled
+    gif.extend_from_slice(&width.to_le_bytes());
+    gif.extend_from_slice(&height.to_le_bytes());
+    gif.extend_from_slice(&[0x80, 0, 0]);
+    match palette {
+        Some(palette) => {
+            gif.extend_from_slice(&palette.empty[..3]);
+            gif.extend_from_slice(&palette.fill[..3]);
+        }
+        None => gif.extend_from_slice(&[255, 255, 255, 0, 0, 0]),
+    }
+
+    // NETSCAPE2.0 application extension: loop forever
+    gif.extend_from_slice(&[0x21, 0xff, 11]);
+    gif.extend_from_slice(b"NETSCAPE2.0");
+    gif.extend_from_slice(&[3, 1, 0, 0, 0]);
+
+    for (display, delay_ms) in frames {
+        // graphic control extension with the frame delay
+        let delay = u32::clamp(delay_ms.div_ceil(10), 2, u16::MAX as u32) as u16;
+        gif.extend_from_slice(&[0x21, 0xf9, 4, 0]);
+        gif.extend_from_slice(&delay.to_le_bytes());
+        gif.extend_from_slice(&[0, 0]);
+
+        // image descriptor covering the whole screen, no local color table
+        gif.push(0x2c);
+        gif.extend_from_slice(&[0, 0, 0, 0]);
+        gif.extend_from_slice(&width.to_le_bytes());
+        gif.extend_from_slice(&height.to_le_bytes());
+        gif.push(0);
+
+        let data = lzw_compress(&color_indices(display, scale as usize), GIF_MIN_CODE_SIZE);
+        gif.push(GIF_MIN_CODE_SIZE as u8);
+        for block in data.chunks(255) {
+            gif.push(block.len() as u8);
+            gif.extend_from_slice(block);
+        }
+        gif.push(0);
+    }
+
+    // trailer
+    gif.push(0x3b);
+    Ok(gif)
+}
+
+/**
+ * One color index per pixel (1 for filled), row by row, with each pixel scaled up
+ */
+fn color_indices(display: &[WordSize], scale: usize) -> Vec<u8> {
+    let words_per_row = (DISPLAY_WIDTH / WORDSIZE) as usize;
+    let mut indices = Vec::with_capacity(display.len() * WORDSIZE as usize * scale * scale);
+    for row in display.chunks(words_per_row) {
+        let line: Vec<u8> = row
+            .iter()
+            .flat_map(|word| (0..WORDSIZE).map(move |bit| ((word >> bit) & 1) as u8))
+            .flat_map(|index| std::iter::repeat_n(index, scale))
+            .collect();
+        for _ in 0..scale {
+            indices.extend_from_slice(&line);
+        }
+    }
+    indices
+}
+
+// GIF requires a minimum code size of at least 2, even for 2 color images
+const GIF_MIN_CODE_SIZE: u32 = 2;
+const LZW_MAX_CODE: u32 = 4096;
+
+/**
+ * Variable code length LZW as used by GIF, codes packed least significant bit first
+ */
+fn lzw_compress(indices: &[u8], min_code_size: u32) -> Vec<u8> {
+    let clear_code = 1 << min_code_size;
+    let end_code = clear_code + 1;
+    let mut writer = BitWriter::new();
+    let mut table: HashMap<(u32, u8), u32> = HashMap::new();
+    let mut code_size = min_code_size + 1;
+    let mut next_code = end_code + 1;
+
+    writer.write_bits(clear_code, code_size);
+    let mut prefix = match indices.first() {
+        Some(index) => *index as u32,
+        None => {
+            writer.write_bits(end_code, code_size);
+            return writer.finish();
+        }
+    };
+
+    for index in &indices[1..] {
+        if let Some(code) = table.get(&(prefix, *index)) {
+            prefix = *code;
+            continue;
+        }
+        writer.write_bits(prefix, code_size);
+        if next_code == LZW_MAX_CODE {
+            // table is full, start over
+            writer.write_bits(clear_code, code_size);
+            table.clear();
+            code_size = min_code_size + 1;
+            next_code = end_code + 1;
+        } else {
+            if next_code >= 1 << code_size {
+                code_size += 1;
+            }
+            table.insert((prefix, *index), next_code);
+            next_code += 1;
+        }
+        prefix = *index as u32;
+    }
+
+    writer.write_bits(prefix, code_size);
+    writer.write_bits(end_code, code_size);
+    writer.finish()
+}
+
+/**
+ * Writes the IHDR chunk and, for paletted images, the PLTE and tRNS chunks
+ */
+pub(crate) fn write_header_chunks(png: &mut Vec<u8>, scale: u32, palette: Option<&Palette>) {
+    let mut header = Vec::new();
+    header.extend_from_slice(&(DISPLAY_WIDTH as u32 * scale).to_be_bytes());
+    header.extend_from_slice(&(DISPLAY_HEIGHT as u32 * scale).to_be_bytes());
+    // bit depth 1, color type 3 (indexed) or 0 (grayscale), default compression, filter, no interlace
+    let color_type = if palette.is_some() { 3 } else { 0 };
+    header.extend_from_slice(&[1, color_type, 0, 0, 0]);
+    write_chunk(png, b"IHDR", &header);
+
+    if let Some(palette) = palette {
+        // index 0 is an empty pixel, index 1 a filled pixel
+        let mut colors = palette.empty[..3].to_vec();
+        colors.extend_from_slice(&palette.fill[..3]);
+        write_chunk(png, b"PLTE", &colors);
+        if palette.empty[3] != 255 || palette.fill[3] != 255 {
+            write_chunk(png, b"tRNS", &[palette.empty[3], palette.fill[3]]);
+        }
+    }
+}
+
+/**
+ * Builds the filtered PNG scanlines for a 1 bit image of the display
+ */
+pub(crate) fn scanlines(display: &[WordSize], scale: u32, palette: Option<&Palette>) -> Vec<u8> {
+    let scale = scale as usize;
+    let words_per_row = (DISPLAY_WIDTH / WORDSIZE) as usize;
+    let row_bytes = (DISPLAY_WIDTH as usize * scale).div_ceil(8);
+    let mut data = Vec::with_capacity((row_bytes + 1) * DISPLAY_HEIGHT as usize * scale);
+
+    for row in display.chunks(words_per_row) {
+        let mut line = vec![0u8; row_bytes];
+        let mut x = 0;
+        for word in row {
+            for bit in 0..WORDSIZE {
+                let filled = (word >> bit) & 1 == 1;
+                // grayscale images draw filled pixels black (0) on white (1)
+                let value = if palette.is_some() { filled } else { !filled };
+                for _ in 0..scale {
+                    if value {
+                        line[x / 8] |= 0x80 >> (x % 8);
+                    }
+                    x += 1;
+                }
+            }
+        }
+        for _ in 0..scale {
+            // filter type 0: none
+            data.push(0);
+            data.extend_from_slice(&line);
+        }
+    }
+    data
+}
+
+pub(crate) fn write_chunk(png: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(chunk_type);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/**
+ * Wraps deflate compressed data in a zlib stream
+ */
+pub(crate) fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    // deflate with a 32K window, no preset dictionary
+    let mut stream = vec![0x78, 0x01];
+    stream.extend(deflate(data));
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+// Deflate length and distance code tables (RFC 1951, section 3.2.5)
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+const HASH_SIZE: usize = 1 << 15;
+
+/**
+ * Writes bits least significant first, as deflate expects
+ */
+struct BitWriter {
+    bytes: Vec<u8>,
+    bit_buffer: u32,
+    bit_count: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            bytes: Vec::new(),
+            bit_buffer: 0,
+            bit_count: 0,
+        }
+    }
+
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.bit_buffer |= value << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.bytes.push(self.bit_buffer as u8);
+            self.bit_buffer >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    /**
+     * Huffman codes are packed starting with their most significant bit
+     */
+    fn write_code(&mut self, code: u32, length: u32) {
+        let reversed = code.reverse_bits() >> (32 - length);
+        self.write_bits(reversed, length);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_count > 0 {
+            self.bytes.push(self.bit_buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+/**
+ * Writes a literal/length symbol with the fixed huffman code
+ */
+fn write_symbol(writer: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => writer.write_code(0x30 + symbol, 8),
+        144..=255 => writer.write_code(0x190 + symbol - 144, 9),
+        256..=279 => writer.write_code(symbol - 256, 7),
+        _ => writer.write_code(0xc0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(writer: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_BASE
+        .iter()
+        .rposition(|base| *base as usize <= length)
+        .unwrap();
+    write_symbol(writer, 257 + code as u32);
+    writer.write_bits(
+        (length - LENGTH_BASE[code] as usize) as u32,
+        LENGTH_EXTRA[code] as u32,
+    );
+
+    let code = DISTANCE_BASE
+        .iter()
+        .rposition(|base| *base as usize <= distance)
+        .unwrap();
+    writer.write_code(code as u32, 5);
+    writer.write_bits(
+        (distance - DISTANCE_BASE[code] as usize) as u32,
+        DISTANCE_EXTRA[code] as u32,
+    );
+}
+
+fn hash(data: &[u8], position: usize) -> usize {
+    let value = (data[position] as usize) << 16
+        | (data[position + 1] as usize) << 8
+        | data[position + 2] as usize;
+    (value.wrapping_mul(2654435761) >> 8) % HASH_SIZE
+}
+
+/**
+ * Adds position to the front of the hash chain for the 3 bytes starting there
+ */
+fn insert_hash(data: &[u8], head: &mut [usize], previous: &mut [usize], position: usize) {
+    if position + MIN_MATCH <= data.len() {
+        let h = hash(data, position);
+        previous[position] = head[h];
+        head[h] = position;
+    }
+}
+
+/**
+ * Compresses data as a single deflate block using the fixed huffman codes.
+ * Matches are found with hash chains over a 32K window, which suits the long runs
+ * of identical bytes that display images are made of.
+ */
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    // final block, fixed huffman codes
+    writer.write_bits(1, 1);
+    writer.write_bits(1, 2);
+
+    let mut head = vec![usize::MAX; HASH_SIZE];
+    let mut previous = vec![usize::MAX; data.len()];
+    let mut position = 0;
+
+    while position < data.len() {
+        let mut best_length = 0;
+        let mut best_distance = 0;
+
+        if position + MIN_MATCH <= data.len() {
+            let mut candidate = head[hash(data, position)];
+            let mut chain = 0;
+            let max_length = usize::min(MAX_MATCH, data.len() - position);
+            while candidate != usize::MAX
+                && position - candidate <= WINDOW_SIZE
+                && chain < MAX_CHAIN
+            {
+                let length = (0..max_length)
+                    .take_while(|i| data[candidate + i] == data[position + i])
+                    .count();
+                if length > best_length {
+                    best_length = length;
+                    best_distance = position - candidate;
+                    if length == max_length {
+                        break;
+                    }
+                }
+                candidate = previous[candidate];
+                chain += 1;
+            }
+        }
+        insert_hash(data, &mut head, &mut previous, position);
+
+        if best_length >= MIN_MATCH {
+            write_match(&mut writer, best_length, best_distance);
+            // index the skipped positions so later matches can refer to them
+            for skipped in position + 1..position + best_length {
+                insert_hash(data, &mut head, &mut previous, skipped);
+            }
+            position += best_length;
+        } else {
+            write_symbol(&mut writer, data[position] as u32);
+            position += 1;
+        }
+    }
+
+    // end of block
+    write_symbol(&mut writer, 256);
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::WORDSIZE;
+
+    /**
+     * A display with a mix of long runs and noise, so the encoder has to emit
+     * both matches and literals
+     */
+    fn test_display() -> Vec<WordSize> {
+        let mut state = 0x2545_f491u32;
+        (0..(DISPLAY_WIDTH / WORDSIZE * DISPLAY_HEIGHT) as usize)
+            .map(|i| match (i / 32) % 4 {
+                0 => 0,
+                1 => -1,
+                2 => 0x0f0f,
+                _ => {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    state as WordSize
+                }
+            })
+            .collect()
+    }
+
+    fn pixel(display: &[WordSize], x: usize, y: usize) -> bool {
+        let word = display[y * (DISPLAY_WIDTH / WORDSIZE) as usize + x / 16];
+        (word >> (x % 16)) & 1 == 1
+    }
+
+    /**
+     * Decodes a PNG to one byte per pixel, gray level or palette index
+     */
+    fn decode_png(png: &[u8]) -> (png::OutputInfo, Vec<u8>) {
+        let mut decoder = png::Decoder::new(png);
+        decoder.set_transformations(png::Transformations::IDENTITY);
+        let mut reader = decoder.read_info().unwrap();
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).unwrap();
+        let pixels = unpack_bits(&buffer, &info);
+        (info, pixels)
+    }
+
+    fn unpack_bits(buffer: &[u8], info: &png::OutputInfo) -> Vec<u8> {
+        let width = info.width as usize;
+        buffer
+            .chunks(info.line_size)
+            .take(info.height as usize)
+            .flat_map(|line| (0..width).map(move |x| (line[x / 8] >> (7 - x % 8)) & 1))
+            .collect()
+    }
+
+    #[test]
+    fn crc32_matches_known_vectors() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+    }
+
+    #[test]
+    fn adler32_matches_known_vectors() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        assert_eq!(adler32(b"abc"), 0x024d_0127);
+        // long enough for both sums to wrap around the modulus many times
+        let data = [0xff; 6000];
+        let a = 1 + 0xff * data.len() as u64;
+        let b: u64 = (1..=data.len() as u64).map(|n| 1 + 0xff * n).sum();
+        assert_eq!(adler32(&data), (((b % 65521) << 16) | (a % 65521)) as u32);
+    }
+
+    #[test]
+    fn png_round_trips_grayscale() {
+        let display = test_display();
+        for scale in [1, 3] {
+            let (info, pixels) = decode_png(&encode_png(&display, scale, None));
+            assert_eq!(info.width, DISPLAY_WIDTH as u32 * scale);
+            assert_eq!(info.height, DISPLAY_HEIGHT as u32 * scale);
+            assert_eq!(info.color_type, png::ColorType::Grayscale);
+            let scale = scale as usize;
+            for (i, value) in pixels.iter().enumerate() {
+                let (x, y) = (
+                    i % (DISPLAY_WIDTH as usize * scale),
+                    i / (DISPLAY_WIDTH as usize * scale),
+                );
+                // filled pixels are black
+                assert_eq!(
+                    *value == 0,
+                    pixel(&display, x / scale, y / scale),
+                    "pixel {}, {}",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn png_round_trips_palette() {
+        let display = test_display();
+        let palette = Palette::from_rgba(0x00ff_0080, 0x0a0a_0aff);
+        let png = encode_png(&display, 1, Some(&palette));
+        let decoder = png::Decoder::new(&png[..]);
+        let reader = decoder.read_info().unwrap();
+        let png_info = reader.info();
+        assert_eq!(
+            png_info.palette.as_deref(),
+            Some(&[10, 10, 10, 0, 255, 0][..])
+        );
+        assert_eq!(png_info.trns.as_deref(), Some(&[255, 128][..]));
+
+        let (info, pixels) = decode_png(&png);
+        assert_eq!(info.color_type, png::ColorType::Indexed);
+        for (i, index) in pixels.iter().enumerate() {
+            let (x, y) = (i % DISPLAY_WIDTH as usize, i / DISPLAY_WIDTH as usize);
+            assert_eq!(*index == 1, pixel(&display, x, y));
+        }
+    }
+
+    fn blank_display() -> Vec<WordSize> {
+        vec![0; (DISPLAY_WIDTH / WORDSIZE * DISPLAY_HEIGHT) as usize]
+    }
+
+    #[test]
+    fn gif_round_trips() {
+        let first = test_display();
+        let second = blank_display();
+        let frames: Vec<(&[WordSize], u32)> = vec![(&first, 120), (&second, 5)];
+        for scale in [1, 2] {
+            let gif = encode_gif(&frames, scale, None).unwrap();
+            let mut options = gif::DecodeOptions::new();
+            options.set_color_output(gif::ColorOutput::Indexed);
+            let mut decoder = options.read_info(&gif[..]).unwrap();
+            assert_eq!(decoder.width() as u32, DISPLAY_WIDTH as u32 * scale);
+            assert_eq!(decoder.height() as u32, DISPLAY_HEIGHT as u32 * scale);
+            assert_eq!(
+                decoder.global_palette(),
+                Some(&[255, 255, 255, 0, 0, 0][..])
+            );
+
+            let mut decoded = Vec::new();
+            while let Some(frame) = decoder.read_next_frame().unwrap() {
+                decoded.push((frame.delay, frame.buffer.to_vec()));
+            }
+            // delays are in hundredths of a second, with a 20 ms minimum
+            assert_eq!(decoded.len(), 2);
+            assert_eq!(decoded[0].0, 12);
+            assert_eq!(decoded[1].0, 2);
+            assert_eq!(decoded[0].1, color_indices(&first, scale as usize));
+            assert_eq!(decoded[1].1, color_indices(&second, scale as usize));
+        }
+    }
+
+    #[test]
+    fn gif_rejects_scales_above_the_maximum() {
+        // no frames, so only the header is encoded
+        let header = encode_gif(&[], MAX_SCALE, None).unwrap();
+        assert_eq!(&header[6..10], &[0x00, 0x20, 0x00, 0x10]);
+        assert_eq!(
+            encode_gif(&[], MAX_SCALE + 1, None),
+            Err("GIF scale must be from 1 to 16, got 17".to_string())
+        );
+        assert!(encode_gif(&[], 127, None).is_err());
+        assert!(encode_gif(&[], 0, None).is_err());
+        assert!(encode_gif(&[], u32::MAX, None).is_err());
+    }
+
+    #[test]
+    fn apng_round_trips() {
+        let first = test_display();
+        let second = blank_display();
+        let frames: Vec<(&[WordSize], u32)> = vec![(&first, 250), (&second, 40)];
+        let apng = encode_apng(&frames, 1, None);
+
+        let mut decoder = png::Decoder::new(&apng[..]);
+        decoder.set_transformations(png::Transformations::IDENTITY);
+        let mut reader = decoder.read_info().unwrap();
+        let animation = reader.info().animation_control.unwrap();
+        assert_eq!(animation.num_frames, 2);
+        assert_eq!(animation.num_plays, 0);
+
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        for (display, delay_ms) in &frames {
+            let info = reader.next_frame(&mut buffer).unwrap();
+            let control = reader.info().frame_control.unwrap();
+            assert_eq!(
+                (control.delay_num, control.delay_den),
+                (*delay_ms as u16, 1000)
+            );
+            let pixels = unpack_bits(&buffer, &info);
+            for (i, value) in pixels.iter().enumerate() {
+                let (x, y) = (i % DISPLAY_WIDTH as usize, i / DISPLAY_WIDTH as usize);
+                assert_eq!(*value == 0, pixel(display, x, y));
+            }
+        }
+    }
+}
@@ -1,45 +1,159 @@
 /* This module contains implementations for the Jack standard library functions
  * Some things to note:
- *  1. readInt(), readChar(), and readLine() are implemented directly in Jack Bytecode, located in the jack_lib_functions directory
- *  2. error(s) logs the output to console.log via the wasm macro
+ *  1. readInt(), readChar(), and readLine() start a KeyRead, and the program waits for the keys without executing commands
+ *  2. error(code) prints ERR<code> on screen like the reference OS, and keeps the code and a description for the Program API
  *  3. halt() sets the finished bit in the memory module
- *  4. wait(n) waits for the time it takes n to count down in a while loop - the exact time is architecture dependent
- *  5. draw functions update both the canvas and the memory array corresponding to the display
+ *  4. wait(n) suspends the program until its virtual clock, advanced by the host, has moved on n ms
+ *  5. draw functions only update the memory array corresponding to the display, the canvas is redrawn from it by the memory module
  * */
 
 
-use std::cmp::{max, min};
-use wasm_bindgen::Clamped;
-use wasm_bindgen_test::console_log;
-use web_sys::ImageData;
-// use web_sys::CanvasRenderingContext2d;
+use std::cmp::max;
 
-use crate::memory::{
-    Memory, WordSize, DISPLAY_HEIGHT, DISPLAY_WIDTH, EMPTY_COLOR, EMPTY_COLOR_ARR, FILL_COLOR,
-    FILL_COLOR_ARR, WORDSIZE,
-};
+use crate::memory::{Memory, WordSize, DISPLAY_HEIGHT, DISPLAY_WIDTH, RANDOM_SEED, WORDSIZE};
+use crate::sound::Voice;
+use crate::text::{CHAR_HEIGHT, COLS, LINES};
 
 pub type NativeFunction = fn(&mut Memory, WordSize) -> WordSize;
 
-const LINES: WordSize = 23;
-const COLS: WordSize = 64;
+/**
+ * Functions web-jack adds to the standard Jack OS API
+ * Programs using them won't run on the nand2tetris tools, so strict mode turns them off
+ */
+pub const EXTENSIONS: [&str; 25] = [
+    "Math.pow",
+    "Mod.mod",
+    "Screen.fillScreen",
+    "Screen.drawRectangleOutline",
+    "Random.seed",
+    "Random.next",
+    "Random.range",
+    "Sys.time",
+    "Sys.ticks",
+    "Keyboard.isDown",
+    "Keyboard.wasPressed",
+    "Keyboard.wasReleased",
+    "Mouse.x",
+    "Mouse.y",
+    "Mouse.buttons",
+    "Mouse.clicked",
+    "Gamepad.buttons",
+    "Gamepad.isDown",
+    "Gamepad.wasPressed",
+    "Sound.tone",
+    "Sound.noise",
+    "Sound.stop",
+    "Storage.write",
+    "Storage.read",
+    "Storage.delete",
+];
+
 const VOID: WordSize = 0;
-const CHAR_HEIGHT: WordSize = 11;
-const CHAR_WIDTH: WordSize = 8;
+const TRUE: WordSize = -1;
+const FALSE: WordSize = 0;
+pub(crate) const NEWLINE: WordSize = 128;
+const BACKSPACE: WordSize = 129;
+
+// Jack OS error codes, as listed in the nand2tetris Sys.error documentation
+const SYS_WAIT_NEGATIVE_DURATION: WordSize = 1;
+const ARRAY_NEW_NON_POSITIVE_SIZE: WordSize = 2;
+const MATH_DIVIDE_BY_ZERO: WordSize = 3;
+const MATH_SQRT_NEGATIVE: WordSize = 4;
+const MEMORY_ALLOC_NON_POSITIVE_SIZE: WordSize = 5;
+const MEMORY_ALLOC_HEAP_OVERFLOW: WordSize = 6;
+const SCREEN_DRAW_PIXEL_ILLEGAL_COORDS: WordSize = 7;
+const SCREEN_DRAW_LINE_ILLEGAL_COORDS: WordSize = 8;
+const SCREEN_DRAW_RECTANGLE_ILLEGAL_COORDS: WordSize = 9;
+const SCREEN_DRAW_CIRCLE_ILLEGAL_CENTER: WordSize = 12;
+const SCREEN_DRAW_CIRCLE_ILLEGAL_RADIUS: WordSize = 13;
+const STRING_NEW_NEGATIVE_LENGTH: WordSize = 14;
+const STRING_CHAR_AT_ILLEGAL_INDEX: WordSize = 15;
+const STRING_SET_CHAR_AT_ILLEGAL_INDEX: WordSize = 16;
+const STRING_FULL: WordSize = 17;
+const STRING_EMPTY: WordSize = 18;
+const STRING_SET_INT_INSUFFICIENT_CAPACITY: WordSize = 19;
+const OUTPUT_MOVE_CURSOR_ILLEGAL_POSITION: WordSize = 20;
+
+/**
+ * Describes a Jack OS error code, following the table in the nand2tetris OS documentation
+ */
+pub fn error_description(error_code: WordSize) -> Option<&'static str> {
+    match error_code {
+        SYS_WAIT_NEGATIVE_DURATION => Some("Sys.wait: duration must be positive"),
+        ARRAY_NEW_NON_POSITIVE_SIZE => Some("Array.new: array size must be positive"),
+        MATH_DIVIDE_BY_ZERO => Some("Math.divide: division by zero"),
+        MATH_SQRT_NEGATIVE => Some("Math.sqrt: cannot compute square root of a negative number"),
+        MEMORY_ALLOC_NON_POSITIVE_SIZE => Some("Memory.alloc: allocated memory size must be positive"),
+        MEMORY_ALLOC_HEAP_OVERFLOW => Some("Memory.alloc: heap overflow"),
+        SCREEN_DRAW_PIXEL_ILLEGAL_COORDS => Some("Screen.drawPixel: illegal pixel coordinates"),
+        SCREEN_DRAW_LINE_ILLEGAL_COORDS => Some("Screen.drawLine: illegal line coordinates"),
+        SCREEN_DRAW_RECTANGLE_ILLEGAL_COORDS => {
+            Some("Screen.drawRectangle: illegal rectangle coordinates")
+        }
+        SCREEN_DRAW_CIRCLE_ILLEGAL_CENTER => Some("Screen.drawCircle: illegal center coordinates"),
+        SCREEN_DRAW_CIRCLE_ILLEGAL_RADIUS => Some("Screen.drawCircle: illegal radius"),
+        STRING_NEW_NEGATIVE_LENGTH => Some("String.new: maximum length must be non-negative"),
+        STRING_CHAR_AT_ILLEGAL_INDEX => Some("String.charAt: string index out of bounds"),
+        STRING_SET_CHAR_AT_ILLEGAL_INDEX => Some("String.setCharAt: string index out of bounds"),
+        STRING_FULL => Some("String.appendChar: string is full"),
+        STRING_EMPTY => Some("String.eraseLastChar: string is empty"),
+        STRING_SET_INT_INSUFFICIENT_CAPACITY => Some("String.setInt: insufficient string capacity"),
+        OUTPUT_MOVE_CURSOR_ILLEGAL_POSITION => Some("Output.moveCursor: illegal cursor location"),
+        _ => None,
+    }
+}
+
+/**
+ * Halts the program with a Jack OS error code, the way Sys.error does:
+ * ERR<code> is printed at the cursor and the program stops
+ */
+fn raise_error(memory: &mut Memory, error_code: WordSize) {
+    for character in format!("ERR{}", error_code).bytes() {
+        output_char_helper(memory, character as WordSize);
+    }
+    memory.error_code.get_or_insert(error_code);
+    let message = match error_description(error_code) {
+        Some(description) => format!("ERR{}: {}", error_code, description),
+        None => format!("ERR{}", error_code),
+    };
+    memory.raise_fault(message);
+}
+
+/**
+ * Allocates a heap block, raising the heap overflow error if there is no room for it
+ */
+fn alloc_helper(memory: &mut Memory, size: WordSize) -> WordSize {
+    match memory.alloc(size) {
+        Some(pointer) => pointer,
+        None => {
+            raise_error(memory, MEMORY_ALLOC_HEAP_OVERFLOW);
+            VOID
+        }
+    }
+}
 
 // MATH
+// Arithmetic wraps around on overflow like the 16 bit Hack ALU
 pub fn multiply(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 2);
     let a = memory.get_arg(0);
     let b = memory.get_arg(1);
-    a * b
+    a.wrapping_mul(b)
 }
 
+/**
+ * Integer division, rounding towards zero
+ * Raises error 3 when dividing by zero
+ */
 pub fn divide(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 2);
     let a = memory.get_arg(0);
     let b = memory.get_arg(1);
-    a / b
+    if b == 0 {
+        raise_error(memory, MATH_DIVIDE_BY_ZERO);
+        return VOID;
+    }
+    a.wrapping_div(b)
 }
 
 pub fn jack_min(memory: &mut Memory, args: WordSize) -> WordSize {
@@ -56,30 +170,57 @@ pub fn jack_max(memory: &mut Memory, args: WordSize) -> WordSize {
     i16::max(a, b)
 }
 
+/**
+ * Integer part of the square root
+ * Raises error 4 for negative values
+ */
 pub fn jack_sqrt(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 1);
     let a = memory.get_arg(0);
+    if a < 0 {
+        raise_error(memory, MATH_SQRT_NEGATIVE);
+        return VOID;
+    }
     (a as f32).sqrt() as i16
 }
 
+/**
+ * a to the power of b. Negative powers round towards zero like integer division.
+ */
 pub fn jack_pow(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 2);
     let a = memory.get_arg(0);
     let b = memory.get_arg(1);
-    a.pow(b as u32)
+    if b >= 0 {
+        return a.wrapping_pow(b as u32);
+    }
+    match a {
+        1 => 1,
+        -1 if b % 2 == 0 => 1,
+        -1 => -1,
+        _ => 0,
+    }
 }
 
 pub fn jack_abs(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 1);
     let a = memory.get_arg(0);
-    i16::abs(a)
+    i16::wrapping_abs(a)
 }
 
+/**
+ * Remainder of a / b, with the sign of a
+ * Raises error 3 when dividing by zero
+ */
 pub fn jack_mod(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 2);
     let a = memory.get_arg(0);
     let b = memory.get_arg(1);
-    a % b
+    if b == 0 {
+        raise_error(memory, MATH_DIVIDE_BY_ZERO);
+        return VOID;
+    }
+    a.wrapping_rem(b)
 }
 
 // STRING
@@ -89,19 +230,27 @@ pub fn jack_mod(memory: &mut Memory, args: WordSize) -> WordSize {
 // string: an array of chars
 /**
  * Allocates a new string of length max_length
+ * Raises error 14 for a negative max_length
  * arg0: max_length
  * returns: pointer to string object
  */
 pub fn string_new(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 1);
     let max_length = memory.get_arg(0);
-    let req_size = max_length + 2;
+    if max_length < 0 {
+        raise_error(memory, STRING_NEW_NEGATIVE_LENGTH);
+        return VOID;
+    }
+    let req_size = max_length.saturating_add(2);
     // console_log!("Requesting allocation for string of size {}", req_size);
-    let string_pointer = memory.alloc(req_size);
+    let string_pointer = alloc_helper(memory, req_size);
+    if memory.finished {
+        return VOID;
+    }
     // set length to 0
     memory.poke(string_pointer, 0);
     // set max length
-    memory.poke(string_pointer + 1, max_length);
+    memory.poke_offset(string_pointer, 1, max_length);
     string_pointer
 }
 
@@ -127,11 +276,12 @@ pub fn string_length(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 1);
     let string_pointer = memory.get_arg(0);
     // length value is located at the 0th position from the string pointer
-    *memory.peek(string_pointer)
+    memory.peek(string_pointer)
 }
 
 /**
  * Return character at index
+ * Raises error 15 if the index is not within the string
  * arg0: object pointer
  * arg1: index
  * returns: character value
@@ -140,11 +290,17 @@ pub fn char_at(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 2);
     let string_pointer = memory.get_arg(0);
     let index = memory.get_arg(1);
-    *memory.peek(string_pointer + index + 2)
+    let length = memory.peek(string_pointer);
+    if !(0..length).contains(&index) {
+        raise_error(memory, STRING_CHAR_AT_ILLEGAL_INDEX);
+        return VOID;
+    }
+    memory.peek_offset(string_pointer, index.saturating_add(2))
 }
 
 /**
  * Sets char at index to value
+ * Raises error 16 if the index is not within the string
  * arg0: object pointer
  * arg1: index
  * arg2: value
@@ -155,12 +311,18 @@ pub fn set_char_at(memory: &mut Memory, args: WordSize) -> WordSize {
     let string_pointer = memory.get_arg(0);
     let index = memory.get_arg(1);
     let chararcter = memory.get_arg(2);
-    memory.poke(string_pointer + index + 2, chararcter);
+    let length = memory.peek(string_pointer);
+    if !(0..length).contains(&index) {
+        raise_error(memory, STRING_SET_CHAR_AT_ILLEGAL_INDEX);
+        return VOID;
+    }
+    memory.poke_offset(string_pointer, index.saturating_add(2), chararcter);
     VOID
 }
 
 /**
- * Appends character to end of string. Will not append if string max length would be exceeded.
+ * Appends character to end of string
+ * Raises error 17 if the string is already at its max length
  * arg0: string pointer
  * arg1: character
  * returns: string pointer
@@ -169,65 +331,69 @@ pub fn append_char(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 2);
     let string_pointer = memory.get_arg(0);
     let character = memory.get_arg(1);
-    let length = memory.peek(string_pointer).clone();
-    let max_length = memory.peek(string_pointer + 1).clone();
-    if length < max_length {
-        memory.poke(string_pointer + 2 + length, character);
-        memory.poke(string_pointer, length + 1);
-        string_pointer
-    } else {
-        console_log!("Attempted to append to a string at max_length");
-        string_pointer
+    let length = memory.peek(string_pointer);
+    let max_length = memory.peek_offset(string_pointer, 1);
+    if length >= max_length {
+        raise_error(memory, STRING_FULL);
+        return string_pointer;
     }
+    memory.poke_offset(string_pointer, length.saturating_add(2), character);
+    memory.poke(string_pointer, length + 1);
+    string_pointer
 }
 
 /**
  * Erases last character in string
+ * Raises error 18 if the string is empty
  * arg0: string pointer
  * returns: VOID
  */
 pub fn erase_last_char(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 1);
     let string_pointer = memory.get_arg(0);
-    let length = memory.peek(string_pointer).clone();
+    let length = memory.peek(string_pointer);
+    if length <= 0 {
+        raise_error(memory, STRING_EMPTY);
+        return VOID;
+    }
     memory.poke(string_pointer, length - 1);
     VOID
 }
 
 /**
  * Returns the integer value of a string, until the first non-numeric character
+ * A leading '-' makes the value negative. Values out of range wrap around.
  * arg0: string pointer
  * returns: integer as WordSize
  */
 pub fn int_value(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 1);
-    // Get integer value
     let string_pointer = memory.get_arg(0);
-    let len = memory.peek(string_pointer);
-    let mut string = String::from("");
-    for i in (string_pointer + 2)..(string_pointer + 2 + len) {
-        string.push(*memory.peek(i) as u8 as char)
-    }
-    // Check for a negative value
-    let sign = if string.starts_with('-') { -1 } else { 1 };
-    if sign == -1 {
-        // Remove the '-'
-        string.remove(0);
-    }
-
-    //parse the string into an integer
-    //https://stackoverflow.com/questions/65601579/parse-an-integer-ignoring-any-non-numeric-suffix
-    let number = string
-        .chars()
-        .map(|c| c.to_digit(10))
-        .take_while(|opt| opt.is_some())
-        .fold(0, |acc, digit| acc * 10 + digit.unwrap());
+    let length = memory.peek(string_pointer);
+    let characters: Vec<WordSize> = (0..length)
+        .map(|i| memory.peek_offset(string_pointer, i.saturating_add(2)))
+        .collect();
+    parse_int_helper(&characters)
+}
 
-    (number as i32 * sign) as WordSize
+fn parse_int_helper(characters: &[WordSize]) -> WordSize {
+    let mut characters = characters.iter().peekable();
+    let negative = characters.next_if_eq(&&45).is_some();
+    let value = characters
+        .map_while(|c| char::from_u32(*c as u32).and_then(|c| c.to_digit(10)))
+        .fold(0 as WordSize, |value, digit| {
+            value.wrapping_mul(10).wrapping_add(digit as WordSize)
+        });
+    if negative {
+        value.wrapping_neg()
+    } else {
+        value
+    }
 }
 
 /**
  * Sets value of string to the string representation of an integer
+ * Raises error 19 if the string's max length is too short for it
  * arg0: string pointer
  * arg1: value
  * returns: VOID
@@ -235,17 +401,17 @@ pub fn int_value(memory: &mut Memory, args: WordSize) -> WordSize {
 pub fn set_int(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 2);
     let string_pointer = memory.get_arg(0);
-    // Get integer value
-    let mut value = memory.get_arg(1);
-    // Start at third position in String
-    let mut position = 2;
-    // Fill with value
-    while value != 0 {
-        memory.poke(string_pointer + position, value % 10);
-        value = value / 10;
-        position += 1;
+    let value = memory.get_arg(1);
+    let max_length = memory.peek_offset(string_pointer, 1);
+    let digits = value.to_string();
+    if digits.len() > max_length as usize {
+        raise_error(memory, STRING_SET_INT_INSUFFICIENT_CAPACITY);
+        return VOID;
     }
-
+    for (position, character) in (2..).zip(digits.bytes()) {
+        memory.poke_offset(string_pointer, position, character as WordSize);
+    }
+    memory.poke(string_pointer, digits.len() as WordSize);
     VOID
 }
 
@@ -253,7 +419,7 @@ pub fn set_int(memory: &mut Memory, args: WordSize) -> WordSize {
  * returns backspace character (129)
  */
 pub fn string_backspace(_memory: &mut Memory, _args: WordSize) -> WordSize {
-    129
+    BACKSPACE
 }
 
 /**
@@ -267,20 +433,25 @@ pub fn double_quote(_memory: &mut Memory, _args: WordSize) -> WordSize {
  * returns newline character (128)
  */
 pub fn new_line(_memory: &mut Memory, _args: WordSize) -> WordSize {
-    128
+    NEWLINE
 }
 
 // ARRAY
 /**
  * Allocates a new array of size
+ * Raises error 2 if size is not positive
  * arg0: size
  * returns: pointer to array
  */
 pub fn array_new(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 1);
     let size = memory.get_arg(0);
+    if size <= 0 {
+        raise_error(memory, ARRAY_NEW_NON_POSITIVE_SIZE);
+        return VOID;
+    }
     // console_log!("Requesting allocation for array of size {}", size);
-    memory.alloc(size)
+    alloc_helper(memory, size)
 }
 
 /**
@@ -297,48 +468,15 @@ pub fn array_dispose(memory: &mut Memory, args: WordSize) -> WordSize {
 }
 
 // OUTPUT
-// The screen is mapped to 24 rows of 64 characters, with each character
+// The screen is mapped to 23 rows of 64 characters, with each character
 // being 8 pixels wide and 11 pixels high, including margins
 fn print_char_helper(memory: &mut Memory, character: &WordSize) {
-    console_log!("in print_char_helper({})", character);
     let bitmap = memory.char_map.get_bitmap(character).clone();
 
     // 32 words in a display line
     // each cursor line covers 11 display lines
-    // draw to canvas
-
-    // bitmap has a vec of U8 values -> [12,30,30,30,12,12,0,12,12,0,0]
-    // for each value, we need to turn it into 8 pixels
-    // each pixel is 4 values: RGBA
-    // if the pixel is 1, we want RGBA == 0, 255, 0, 255
-    // if pixel is 0, we want RGBA == 10, 10, 10, 255
-    let mut pixel_data = Vec::new();
-
-    for line in bitmap.clone() {
-        for i in 0..8 {
-            let bit = (line >> i) & 1;
-            if bit == 1 {
-                pixel_data.extend_from_slice(&FILL_COLOR_ARR[..]);
-            } else {
-                pixel_data.extend_from_slice(&EMPTY_COLOR_ARR[..]);
-            }
-        }
-    }
-
-    let slice_data = Clamped(&pixel_data[..]);
-    let pixel_row = ImageData::new_with_u8_clamped_array(slice_data, CHAR_WIDTH as u32)
-        .expect("Error creating ImageData");
-    memory
-        .canvas_context
-        .put_image_data(
-            &pixel_row,
-            (memory.cursor_col * CHAR_WIDTH).into(),
-            (memory.cursor_line * CHAR_HEIGHT).into(),
-        )
-        .expect("Error when placing char into canvas");
-
     // set the memory value in the display mapped memory
-    for char_row in 0..11 {
+    for char_row in 0..CHAR_HEIGHT {
         let address = (DISPLAY_WIDTH / WORDSIZE) * (memory.cursor_line * CHAR_HEIGHT + char_row)
             + memory.cursor_col / 2;
         // even cursor colums change the first half of the word (marked by X) -> 00000000XXXXXXXX
@@ -353,6 +491,23 @@ fn print_char_helper(memory: &mut Memory, character: &WordSize) {
             memory.set_display_word(address, new_value);
         }
     }
+    // recorded after drawing, since drawing clears the cell in the text grid
+    memory.text.set(memory.cursor_line, memory.cursor_col, *character);
+}
+
+/**
+ * Prints a character at the cursor and advances it
+ * newline and backspace move the cursor instead of being drawn
+ */
+fn output_char_helper(memory: &mut Memory, character: WordSize) {
+    match character {
+        NEWLINE => newline_helper(memory),
+        BACKSPACE => backspace_helper(memory),
+        _ => {
+            print_char_helper(memory, &character);
+            step_cursor_helper(memory);
+        }
+    }
 }
 
 /**
@@ -361,8 +516,7 @@ fn print_char_helper(memory: &mut Memory, character: &WordSize) {
  */
 fn step_cursor_helper(memory: &mut Memory) {
     if memory.cursor_col == COLS - 1 {
-        memory.cursor_col = 0;
-        memory.cursor_line = (memory.cursor_line + 1) % (LINES);
+        newline_helper(memory);
     } else {
         memory.cursor_col += 1;
     }
@@ -370,15 +524,38 @@ fn step_cursor_helper(memory: &mut Memory) {
 
 /**
  * moves cursor to start of new line
+ * Past the last line the cursor wraps to the top, or the text scrolls up a line in scrolling mode
  * returns: void
  */
 fn newline_helper(memory: &mut Memory) {
-    memory.cursor_line = (memory.cursor_line + 1) % (LINES - 1);
     memory.cursor_col = 0;
+    if memory.cursor_line < LINES - 1 {
+        memory.cursor_line += 1;
+    } else if memory.output_scrolling {
+        memory.scroll_text_up();
+    } else {
+        memory.cursor_line = 0;
+    }
+}
+
+/**
+ * Moves cursor one location back and erases the character there
+ * From the start of a line the cursor moves to the end of the previous one
+ * returns: void
+ */
+fn backspace_helper(memory: &mut Memory) {
+    if memory.cursor_col > 0 {
+        memory.cursor_col -= 1;
+    } else if memory.cursor_line > 0 {
+        memory.cursor_line -= 1;
+        memory.cursor_col = COLS - 1;
+    }
+    // print blank space without advancing cursor
+    print_char_helper(memory, &32);
 }
 
 /**
- * Moves cursor to line and col specified
+ * Moves cursor to line and col specified, erasing the character there
  * arg0: line
  * arg1: col
  * returns: VOID
@@ -387,9 +564,13 @@ pub fn move_cursor(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 2);
     let line = memory.get_arg(0);
     let col = memory.get_arg(1);
-    assert!(line < LINES && col < COLS);
+    if !(0..LINES).contains(&line) || !(0..COLS).contains(&col) {
+        raise_error(memory, OUTPUT_MOVE_CURSOR_ILLEGAL_POSITION);
+        return VOID;
+    }
     memory.cursor_line = line;
     memory.cursor_col = col;
+    print_char_helper(memory, &32);
     VOID
 }
 
@@ -400,10 +581,8 @@ pub fn move_cursor(memory: &mut Memory, args: WordSize) -> WordSize {
  */
 pub fn print_char(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 1);
-    let c = &memory.get_arg(0);
-    // console_log!("in print_char({})", c);
-    print_char_helper(memory, c);
-    step_cursor_helper(memory);
+    let c = memory.get_arg(0);
+    output_char_helper(memory, c);
     VOID
 }
 
@@ -416,15 +595,21 @@ pub fn print_string(memory: &mut Memory, args: WordSize) -> WordSize {
     //s is a pointer to a string object in memory
     assert!(args == 1);
     let string_pointer = memory.get_arg(0);
-    let length = memory.peek(string_pointer).clone();
-    for character_pointer in (string_pointer + 2)..(string_pointer + 2 + length) {
-        let character = memory.peek(character_pointer).clone();
-        print_char_helper(memory, &character);
-        step_cursor_helper(memory);
-    }
+    print_string_helper(memory, string_pointer);
     VOID
 }
 
+fn print_string_helper(memory: &mut Memory, string_pointer: WordSize) {
+    let length = memory.peek(string_pointer);
+    for i in 0..length {
+        let character = memory.peek_offset(string_pointer, i.saturating_add(2));
+        if memory.finished {
+            break;
+        }
+        output_char_helper(memory, character);
+    }
+}
+
 /**
  * Prints integer value. Handles positive and negative values.
  * arg0: int
@@ -432,28 +617,14 @@ pub fn print_string(memory: &mut Memory, args: WordSize) -> WordSize {
  */
 pub fn print_int(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 1);
-    let mut i = memory.get_arg(0);
-    let mut digits = vec![];
-    let sign: WordSize = if i < 0 { -1 } else { 1 };
-
-    console_log!("In print int with value {}", i);
-    i = i * sign;
-    if i == 0 {
-        digits.push(48);
-    }
-    while i != 0 {
-        digits.push((i % 10) + 48); /* value 48 corresponds to a 0 character in the character bitmap */
-        i /= 10;
-    }
-    if sign == -1 {
+    let i = memory.get_arg(0);
+    if i < 0 {
         // Print minus sign
-        print_char_helper(memory, &(45 as WordSize));
-        step_cursor_helper(memory);
+        output_char_helper(memory, 45);
     }
-
-    for d in digits.iter().rev() {
-        print_char_helper(memory, d);
-        step_cursor_helper(memory);
+    // i32 so that the magnitude of -32768 fits
+    for digit in (i as i32).abs().to_string().bytes() {
+        output_char_helper(memory, digit as WordSize);
     }
     VOID
 }
@@ -474,13 +645,18 @@ pub fn println(memory: &mut Memory, args: WordSize) -> WordSize {
  */
 pub fn output_backspace(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 0);
-    memory.cursor_col = i16::max(0, memory.cursor_col - 1);
-    // print blank space without advancing cursor
-    print_char_helper(memory, &32);
+    backspace_helper(memory);
     VOID
 }
 
 // SCREEN
+/**
+ * Checks that x, y is a pixel on the display
+ */
+fn on_display(x: WordSize, y: WordSize) -> bool {
+    (0..DISPLAY_WIDTH).contains(&x) && (0..DISPLAY_HEIGHT).contains(&y)
+}
+
 /**
  * Draws line given coordinates x1, y1, x2, y2
  * Returns: void
@@ -502,6 +678,7 @@ fn draw_line_helper(memory: &mut Memory, x1: WordSize, y1: WordSize, x2: WordSiz
     let mut diff = 0;
 
     match (dx, dy) {
+        (0, 0) => memory.set_display_xy(x1, y1),
         (_, 0) => {
             while i16::abs(a) <= abs_dx {
                 memory.set_display_xy(x1 + a, y1);
@@ -536,16 +713,6 @@ fn draw_line_helper(memory: &mut Memory, x1: WordSize, y1: WordSize, x2: WordSiz
 pub fn clear_screen(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 0);
     memory.clear_display();
-    console_log!("Clearing screen");
-    let saved_fill_style = memory.canvas_context.fill_style();
-    memory.canvas_context.set_fill_style(&EMPTY_COLOR.into());
-    memory.canvas_context.fill_rect(
-        0.into(),
-        0.into(),
-        memory.canvas.width().into(),
-        memory.canvas.height().into(),
-    );
-    memory.canvas_context.set_fill_style(&saved_fill_style);
     VOID
 }
 
@@ -556,18 +723,6 @@ pub fn clear_screen(memory: &mut Memory, args: WordSize) -> WordSize {
 pub fn fill_screen(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 0);
     memory.fill_display();
-
-    // checking if the current fill_style is filled is hard, so we just save the current
-    // fill_style, fill the screen, and revert to the original fill_style
-    let saved_fill_style = memory.canvas_context.fill_style();
-    memory.canvas_context.set_fill_style(&FILL_COLOR.into());
-    memory.canvas_context.fill_rect(
-        0.into(),
-        0.into(),
-        memory.canvas.width().into(),
-        memory.canvas.height().into(),
-    );
-    memory.canvas_context.set_fill_style(&saved_fill_style);
     VOID
 }
 
@@ -580,16 +735,6 @@ pub fn set_color(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 1);
     let color = memory.get_arg(0);
     memory.screen_color = color;
-
-    // set canvas context fill style
-    let value;
-    if color == 0 {
-        value = EMPTY_COLOR
-    } else {
-        value = FILL_COLOR
-    }
-    memory.canvas_context.set_fill_style(&value.into());
-    memory.canvas_context.set_stroke_style(&value.into());
     VOID
 }
 
@@ -603,11 +748,11 @@ pub fn draw_pixel(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 2);
     let x = memory.get_arg(0);
     let y = memory.get_arg(1);
+    if !on_display(x, y) {
+        raise_error(memory, SCREEN_DRAW_PIXEL_ILLEGAL_COORDS);
+        return VOID;
+    }
     memory.set_display_xy(x, y);
-
-    memory
-        .canvas_context
-        .fill_rect(x.into(), y.into(), 1.into(), 1.into());
     VOID
 }
 
@@ -625,16 +770,22 @@ pub fn draw_line(memory: &mut Memory, args: WordSize) -> WordSize {
     let y1 = memory.get_arg(1);
     let x2 = memory.get_arg(2);
     let y2 = memory.get_arg(3);
+    if !on_display(x1, y1) || !on_display(x2, y2) {
+        raise_error(memory, SCREEN_DRAW_LINE_ILLEGAL_COORDS);
+        return VOID;
+    }
     draw_line_helper(memory, x1, y1, x2, y2);
-
-    // draw in canvas
-    memory.canvas_context.begin_path();
-    memory.canvas_context.move_to(x1.into(), y1.into());
-    memory.canvas_context.line_to(x2.into(), y2.into());
-    memory.canvas_context.stroke();
     VOID
 }
 
+/**
+ * Checks rectangle corners the way the Jack OS does:
+ * both corners must be on the display, with x1 <= x2 and y1 <= y2
+ */
+fn valid_rectangle(x1: WordSize, y1: WordSize, x2: WordSize, y2: WordSize) -> bool {
+    on_display(x1, y1) && on_display(x2, y2) && x1 <= x2 && y1 <= y2
+}
+
 /**
  * Draw unfilled rectangle from point x1, y1, to point x2, y2
  * arg0: x1
@@ -648,17 +799,14 @@ pub fn draw_rectangle_outline(memory: &mut Memory, args: WordSize) -> WordSize {
     let y1 = memory.get_arg(1);
     let x2 = memory.get_arg(2);
     let y2 = memory.get_arg(3);
+    if !valid_rectangle(x1, y1, x2, y2) {
+        raise_error(memory, SCREEN_DRAW_RECTANGLE_ILLEGAL_COORDS);
+        return VOID;
+    }
     draw_line_helper(memory, x1, y1, x1, y2);
     draw_line_helper(memory, x2, y1, x2, y2);
     draw_line_helper(memory, x1, y1, x2, y1);
     draw_line_helper(memory, x1, y2, x2, y2);
-
-    // draw in canvas
-    memory.canvas_context.begin_path();
-    memory
-        .canvas_context
-        .rect(x1.into(), y1.into(), (x2 - x1).into(), (y2 - y1).into());
-    memory.canvas_context.stroke();
     VOID
 }
 
@@ -675,26 +823,19 @@ pub fn draw_rectangle(memory: &mut Memory, args: WordSize) -> WordSize {
     let y1 = memory.get_arg(1);
     let x2 = memory.get_arg(2);
     let y2 = memory.get_arg(3);
-    let dy = y2 - y1;
-    let abs_dy = i16::abs(dy);
-    let delta_y = dy.signum();
-    let mut a = 0;
-    while i16::abs(a) <= abs_dy {
-        draw_line_helper(memory, x1, y1 + a, x2, y1 + a);
-        a += delta_y;
+    if !valid_rectangle(x1, y1, x2, y2) {
+        raise_error(memory, SCREEN_DRAW_RECTANGLE_ILLEGAL_COORDS);
+        return VOID;
+    }
+    for row in y1..=y2 {
+        draw_line_helper(memory, x1, row, x2, row);
     }
-
-    // draw in canvas
-    memory.canvas_context.begin_path();
-    memory
-        .canvas_context
-        .rect(x1.into(), y1.into(), (x2 - x1 + 1).into(), (y2 - y1 + 1).into()); // need to add 1 to get correct size
-    memory.canvas_context.fill();
     VOID
 }
 
 /**
  * Draw filled circle with center at point x1, y1, and radius r
+ * Raises error 12 if the center is off the display and error 13 if the circle doesn't fit on it
  * arg0: x1
  * arg1: y1
  * arg2: r
@@ -705,45 +846,22 @@ pub fn draw_circle(memory: &mut Memory, args: WordSize) -> WordSize {
     let x1 = memory.get_arg(0);
     let y1 = memory.get_arg(1);
     let r = memory.get_arg(2);
+    if !on_display(x1, y1) {
+        raise_error(memory, SCREEN_DRAW_CIRCLE_ILLEGAL_CENTER);
+        return VOID;
+    }
+    // the center is on the display, so these can't overflow
+    if r < 0 || !on_display(x1 - r, y1 - r) || !on_display(x1 + r, y1 + r) {
+        raise_error(memory, SCREEN_DRAW_CIRCLE_ILLEGAL_RADIUS);
+        return VOID;
+    }
 
-    // let bottom = max(y1 + r, 0);
-    // let top = min(y1 - r, DISPLAY_HEIGHT - 1);
-    // let left = max(x1 - r, 0);
-    // let right = min(x1 + r, DISPLAY_WIDTH - 1);
-
-    // for row in top..bottom {
-    //     let dy = row - y1;
-    //     let offset = ((i32::pow(r as i32, 2) - i32::pow(dy as i32, 2)) as f32).sqrt() as WordSize;
-    //     if offset > 0 {
-    //         draw_line_helper(
-    //             memory,
-    //             max(left, x1 - offset),
-    //             row,
-    //             min(right, x1 + offset),
-    //             row,
-    //         );
-    //     }
-    // }
-
-    // draw in canvas
-    memory.canvas_context.begin_path();
-    if memory
-        .canvas_context
-        .ellipse(
-            x1.into(),
-            y1.into(),
-            r.into(),
-            r.into(),
-            0.into(),
-            0.into(),
-            (std::f32::consts::PI * 2.0).into(),
-        )
-        .is_err()
-    {
-        console_log!("Error drawing ellipse")
-    };
-    memory.canvas_context.stroke();
-    memory.canvas_context.fill();
+    // fill the circle with horizontal lines
+    for row in (y1 - r)..=(y1 + r) {
+        let dy = (row - y1) as i32;
+        let offset = ((r as i32 * r as i32 - dy * dy) as f32).sqrt() as WordSize;
+        draw_line_helper(memory, x1 - offset, row, x1 + offset, row);
+    }
     VOID
 }
 
@@ -773,24 +891,69 @@ pub fn key_pressed(memory: &mut Memory, args: WordSize) -> WordSize {
     memory.keyboard
 }
 
+/**
+ * Returns true if the key is held down, whichever other keys are held with it
+ * Unlike keyPressed this sees every key that is down, not just the last one pressed
+ * arg0: key code
+ */
+pub fn is_down(memory: &mut Memory, args: WordSize) -> WordSize {
+    assert!(args == 1);
+    let code = memory.get_arg(0);
+    jack_bool(memory.keys.is_down(code))
+}
+
+/**
+ * Returns true if the key was pressed since the last wasPressed call for it
+ * arg0: key code
+ */
+pub fn was_pressed(memory: &mut Memory, args: WordSize) -> WordSize {
+    assert!(args == 1);
+    let code = memory.get_arg(0);
+    jack_bool(memory.keys.take_pressed(code))
+}
+
+/**
+ * Returns true if the key was released since the last wasReleased call for it
+ * arg0: key code
+ */
+pub fn was_released(memory: &mut Memory, args: WordSize) -> WordSize {
+    assert!(args == 1);
+    let code = memory.get_arg(0);
+    jack_bool(memory.keys.take_released(code))
+}
+
+fn jack_bool(value: bool) -> WordSize {
+    if value {
+        TRUE
+    } else {
+        FALSE
+    }
+}
+
 /**
  * Waits until a key is pressed on the keyboard and released,
  * then echoes the key to the screen, and returns the character
  * of the pressed key.
+ * The program waits for the key in the KeyRead this starts, see read_key_helper
  */
-pub fn read_char(_memory: &mut Memory, args: WordSize) -> WordSize {
+pub fn read_char(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 0);
-    panic!("readChar is implemented as a jack function, so don't look here for it")
+    memory.key_read = Some(KeyRead::new(ReadKind::Char, memory.keyboard));
+    VOID
 }
 
 /**
  * Displays the message on the screen, reads from the keyboard the entered
  * text until a newline character is detected, echoes the text to the screen,
  * and returns its value. Also handles user backspaces.
+ * arg0: message string pointer
  */
-pub fn read_line(_memory: &mut Memory, args: WordSize) -> WordSize {
+pub fn read_line(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 1);
-    panic!("readLine is implemented as a jack function, so don't look here for it")
+    let message = memory.get_arg(0);
+    print_string_helper(memory, message);
+    memory.key_read = Some(KeyRead::new(ReadKind::Line, memory.keyboard));
+    VOID
 }
 
 /**
@@ -798,14 +961,127 @@ pub fn read_line(_memory: &mut Memory, args: WordSize) -> WordSize {
  * text until a newline character is detected, echoes the text to the screen,
  * and returns its integer value (until the first non-digit character in the
  * entered text is detected). Also handles user backspaces.
+ * arg0: message string pointer
  */
-pub fn read_int(_memory: &mut Memory, args: WordSize) -> WordSize {
+pub fn read_int(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 1);
-    panic!("readInt is implemented as a jack function, so don't look here for it")
+    let message = memory.get_arg(0);
+    print_string_helper(memory, message);
+    memory.key_read = Some(KeyRead::new(ReadKind::Int, memory.keyboard));
+    VOID
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ReadKind {
+    Char,
+    Line,
+    Int,
+}
+
+/**
+ * A Keyboard.readChar, readLine or readInt waiting for input
+ * While one is in progress the program stops executing commands,
+ * and every step feeds the keyboard register to read_key_helper instead
+ */
+pub struct KeyRead {
+    kind: ReadKind,
+    // key that is down and counts once it is released
+    pressed: Option<WordSize>,
+    line: Vec<WordSize>,
+}
+
+impl KeyRead {
+    /**
+     * A key already down when the read starts counts once it is released
+     */
+    fn new(kind: ReadKind, key: WordSize) -> KeyRead {
+        KeyRead {
+            kind,
+            pressed: (key != 0).then_some(key),
+            line: Vec::new(),
+        }
+    }
+}
+
+/**
+ * Advances the key read in progress with the current keyboard register
+ * A key counts once it is released, then it is echoed and added to the line being read
+ * returns: the value of the read function once it is done
+ */
+pub fn read_key_helper(memory: &mut Memory) -> Option<WordSize> {
+    let mut read = memory.key_read.take()?;
+    let key = memory.keyboard;
+    let character = match read.pressed {
+        None => {
+            if key != 0 {
+                read.pressed = Some(key);
+            }
+            None
+        }
+        Some(pressed) if pressed != key => {
+            read.pressed = None;
+            Some(pressed)
+        }
+        Some(_) => None,
+    };
+    let result = match (character, read.kind) {
+        (None, _) => None,
+        (Some(character), ReadKind::Char) => {
+            // special keys other than newline and backspace have nothing to show
+            if character <= BACKSPACE {
+                output_char_helper(memory, character);
+            }
+            Some(character)
+        }
+        (Some(NEWLINE), ReadKind::Line) => {
+            newline_helper(memory);
+            Some(new_string_helper(memory, &read.line))
+        }
+        (Some(NEWLINE), ReadKind::Int) => {
+            newline_helper(memory);
+            Some(parse_int_helper(&read.line))
+        }
+        (Some(BACKSPACE), _) => {
+            // the prompt before the line can't be erased
+            if read.line.pop().is_some() {
+                backspace_helper(memory);
+            }
+            None
+        }
+        (Some(character), _) => {
+            if character < NEWLINE {
+                read.line.push(character);
+                output_char_helper(memory, character);
+            }
+            None
+        }
+    };
+    if result.is_none() {
+        memory.key_read = Some(read);
+    }
+    result
 }
 
 /**
- * Returns a reference to the value of memory at the index, using the HACK computer memory mapping
+ * Allocates a string holding the characters, with room for at least 64
+ */
+fn new_string_helper(memory: &mut Memory, characters: &[WordSize]) -> WordSize {
+    let length = characters.len() as WordSize;
+    let max_length = max(length, COLS);
+    let string_pointer = alloc_helper(memory, max_length + 2);
+    if memory.finished {
+        return VOID;
+    }
+    memory.poke(string_pointer, length);
+    memory.poke_offset(string_pointer, 1, max_length);
+    for (i, character) in (2..).zip(characters) {
+        memory.poke_offset(string_pointer, i, *character);
+    }
+    string_pointer
+}
+
+/**
+ * Returns the value of memory at the index, using the HACK computer memory mapping
  * ram: 0-16383
  * display: 16384-24575
  * keyboard: 24576
@@ -813,7 +1089,7 @@ pub fn read_int(_memory: &mut Memory, args: WordSize) -> WordSize {
 pub fn jack_peek(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 1);
     let index = memory.get_arg(0);
-    *memory.peek(index)
+    memory.peek(index)
 }
 /**
  * Changes at the index to the provided value, using the HACK computer memory mapping
@@ -832,14 +1108,20 @@ pub fn jack_poke(memory: &mut Memory, args: WordSize) -> WordSize {
 
 /**
  * Allocates a block of memory of at least 'size' words
+ * Raises error 5 if size is negative, and error 6 if the heap is full
  * arg0: size
- * returns VOID
+ * returns: pointer to the block
  */
 pub fn alloc(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 1);
     let size = memory.get_arg(0);
-    console_log!("Allocating {} words on heap\n",size);
-    memory.alloc(size)
+    if size < 0 {
+        raise_error(memory, MEMORY_ALLOC_NON_POSITIVE_SIZE);
+        return VOID;
+    }
+    // constructors of classes without fields allocate 0 words, they still get a block of their own
+    // console_log!("Allocating {} words on heap", size);
+    alloc_helper(memory, max(size, 1))
 }
 /**
  * Frees block of memory pointed to by 'pointer'
@@ -853,14 +1135,220 @@ pub fn de_alloc(memory: &mut Memory, args: WordSize) -> WordSize {
     VOID
 }
 
+// MOUSE
+// A web-jack extension, the registers it reads are described in mouse::Mouse
+/**
+ * Returns the column of the display pixel under the mouse pointer
+ */
+pub fn mouse_x(memory: &mut Memory, args: WordSize) -> WordSize {
+    assert!(args == 0);
+    memory.mouse.x()
+}
+
+/**
+ * Returns the row of the display pixel under the mouse pointer
+ */
+pub fn mouse_y(memory: &mut Memory, args: WordSize) -> WordSize {
+    assert!(args == 0);
+    memory.mouse.y()
+}
+
+/**
+ * Returns the mouse buttons held down: 1 main, 2 secondary, 4 middle, added together
+ */
+pub fn mouse_buttons(memory: &mut Memory, args: WordSize) -> WordSize {
+    assert!(args == 0);
+    memory.mouse.buttons()
+}
+
+/**
+ * Returns the mouse buttons clicked since the last call, as flags like Mouse.buttons
+ */
+pub fn mouse_clicked(memory: &mut Memory, args: WordSize) -> WordSize {
+    assert!(args == 0);
+    memory.mouse.take_clicked()
+}
+
+// GAMEPAD
+// A web-jack extension, the button flags are listed in gamepad.rs
+/**
+ * Returns the gamepad buttons held down, as flags added together
+ */
+pub fn gamepad_buttons(memory: &mut Memory, args: WordSize) -> WordSize {
+    assert!(args == 0);
+    memory.gamepad.buttons()
+}
+
+/**
+ * Returns true if any of the buttons is held down
+ * arg0: button flags
+ */
+pub fn gamepad_is_down(memory: &mut Memory, args: WordSize) -> WordSize {
+    assert!(args == 1);
+    let buttons = memory.get_arg(0);
+    jack_bool(memory.gamepad.buttons() & buttons != 0)
+}
+
+/**
+ * Returns true if any of the buttons was pressed since wasPressed last asked about it
+ * arg0: button flags
+ */
+pub fn gamepad_was_pressed(memory: &mut Memory, args: WordSize) -> WordSize {
+    assert!(args == 1);
+    let buttons = memory.get_arg(0);
+    jack_bool(memory.gamepad.take_pressed(buttons) != 0)
+}
+
+// SOUND
+// A web-jack extension, the samples are generated by sound::SoundBuffer
+/**
+ * Plays a square wave tone, replacing whatever was playing, and returns straight away
+ * arg0: frequency in Hz, a frequency of 0 or less stops the sound
+ * arg1: duration in ms of virtual time
+ */
+pub fn tone(memory: &mut Memory, args: WordSize) -> WordSize {
+    assert!(args == 2);
+    let frequency = memory.get_arg(0);
+    let duration = memory.get_arg(1);
+    memory.voice = if frequency > 0 {
+        Voice::Tone {
+            frequency,
+            until: memory.clock + duration as f64,
+        }
+    } else {
+        Voice::Silent
+    };
+    VOID
+}
+
+/**
+ * Plays white noise, replacing whatever was playing, and returns straight away
+ * arg0: duration in ms of virtual time
+ */
+pub fn noise(memory: &mut Memory, args: WordSize) -> WordSize {
+    assert!(args == 1);
+    let duration = memory.get_arg(0);
+    memory.voice = Voice::Noise {
+        until: memory.clock + duration as f64,
+    };
+    VOID
+}
+
+/**
+ * Stops whatever is playing
+ * Sound is only generated when the host advances the clock, so a sound stopped before
+ * the next frame, without a Sys.wait after starting it, is never heard
+ */
+pub fn stop_sound(memory: &mut Memory, args: WordSize) -> WordSize {
+    assert!(args == 0);
+    memory.voice = Voice::Silent;
+    VOID
+}
+
+// STORAGE
+// A web-jack extension, the host exports and imports the stored entries, see storage.rs
+/**
+ * Saves the first len words of the array under the key, replacing what was saved there
+ * arg0: key string pointer
+ * arg1: array pointer
+ * arg2: number of words to save
+ */
+pub fn storage_write(memory: &mut Memory, args: WordSize) -> WordSize {
+    assert!(args == 3);
+    let Some(key) = storage_key_helper(memory) else {
+        return VOID;
+    };
+    let array = memory.get_arg(1);
+    let length = memory.get_arg(2);
+    if length < 0 {
+        memory.raise_fault(format!("Storage.write: negative length {}", length));
+        return VOID;
+    }
+    let mut words = Vec::with_capacity(length as usize);
+    for i in 0..length {
+        words.push(memory.peek_offset(array, i));
+        if memory.finished {
+            return VOID;
+        }
+    }
+    if let Err(message) = memory.storage.write(key, words) {
+        memory.raise_fault(format!("Storage.write: {}", message));
+    }
+    VOID
+}
+
+/**
+ * Copies the words saved under the key into the array, which must be large enough for them
+ * arg0: key string pointer
+ * arg1: array pointer
+ * returns: the number of words copied, or -1 if nothing is saved under the key
+ */
+pub fn storage_read(memory: &mut Memory, args: WordSize) -> WordSize {
+    assert!(args == 2);
+    let Some(key) = storage_key_helper(memory) else {
+        return VOID;
+    };
+    let array = memory.get_arg(1);
+    let Some(words) = memory.storage.read(&key).map(|words| words.to_vec()) else {
+        return -1;
+    };
+    // entries hold at most WordSize::MAX words
+    let length = words.len() as WordSize;
+    for (i, word) in (0..length).zip(words) {
+        memory.poke_offset(array, i, word);
+        if memory.finished {
+            break;
+        }
+    }
+    length
+}
+
+/**
+ * Removes whatever is saved under the key
+ * arg0: key string pointer
+ */
+pub fn storage_delete(memory: &mut Memory, args: WordSize) -> WordSize {
+    assert!(args == 1);
+    if let Some(key) = storage_key_helper(memory) {
+        memory.storage.delete(&key);
+    }
+    VOID
+}
+
+/**
+ * Reads the string in arg0 as a Rust string
+ * Returns None if the string isn't in memory, after raising a fault
+ */
+fn storage_key_helper(memory: &mut Memory) -> Option<String> {
+    let string_pointer = memory.get_arg(0);
+    let length = memory.peek(string_pointer);
+    let mut key = String::new();
+    for i in 0..length {
+        let character = memory.peek_offset(string_pointer, i.saturating_add(2));
+        if memory.finished {
+            return None;
+        }
+        key.extend(char::from_u32(character as u32));
+    }
+    (!memory.finished).then_some(key)
+}
+
 // SYS
+/**
+ * Asks the program to sleep for duration ms of its virtual clock
+ * Raises error 1 for a negative duration
+ * arg0: duration
+ * returns: VOID
+ */
 pub fn wait(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 1);
-    let mut _wait_time = memory.get_arg(0);
-    // console_log!("Waiting for {}", _wait_time);
-    while _wait_time > 0 {
-        _wait_time -= 1;
+    let duration = memory.get_arg(0);
+    if duration < 0 {
+        raise_error(memory, SYS_WAIT_NEGATIVE_DURATION);
+        return VOID;
     }
+    // console_log!("Waiting for {}", duration);
+    memory.wait_request = Some(duration);
     VOID
 }
 
@@ -875,13 +1363,517 @@ pub fn halt(memory: &mut Memory, args: WordSize) -> WordSize {
 }
 
 /**
- * currently just exits the program by setting the finished bit
+ * Prints ERR<code> on the screen and exits the program by setting the finished bit
  */
 pub fn error(memory: &mut Memory, args: WordSize) -> WordSize {
     assert!(args == 1);
     let error_code = memory.get_arg(0);
-    console_log!("Fatal Error: {}", error_code);
-    memory.finished = true;
-    // this could initially just terminate with the running bit, but if there is an error message we could just use console log, or print it to console
+    raise_error(memory, error_code);
     VOID
 }
+
+// RANDOM
+// Numbers come from a xorshift generator, so a seed always gives the same sequence
+/**
+ * Restarts the random sequence from seed
+ * arg0: seed
+ * returns: VOID
+ */
+pub fn random_seed(memory: &mut Memory, args: WordSize) -> WordSize {
+    assert!(args == 1);
+    let seed = memory.get_arg(0) as u16 as u32;
+    // xorshift gets stuck at a state of 0
+    memory.random_state = match seed.wrapping_mul(0x9E37_79B1) ^ RANDOM_SEED {
+        0 => RANDOM_SEED,
+        state => state,
+    };
+    VOID
+}
+
+/**
+ * returns: the next random number, from 0 to 32767
+ */
+pub fn random_next(memory: &mut Memory, args: WordSize) -> WordSize {
+    assert!(args == 0);
+    next_random_helper(memory)
+}
+
+/**
+ * Returns a random number from low to high, including both, each equally likely
+ * arg0: low
+ * arg1: high
+ * returns: random number, or low if high is below it
+ */
+pub fn random_range(memory: &mut Memory, args: WordSize) -> WordSize {
+    assert!(args == 2);
+    let low = memory.get_arg(0);
+    let high = memory.get_arg(1);
+    random_range_helper(memory, low, high)
+}
+
+fn random_range_helper(memory: &mut Memory, low: WordSize, high: WordSize) -> WordSize {
+    if high < low {
+        return low;
+    }
+    // the span is up to 65536, so it's drawn from 16 random bits, rejecting the draws
+    // past the last whole multiple of the span that would make the low numbers more likely
+    let span = (high as i32 - low as i32 + 1) as u32;
+    let limit = 65536 - 65536 % span;
+    loop {
+        let random = next_random_state(memory) >> 16;
+        if random < limit {
+            return (low as i32 + (random % span) as i32) as WordSize;
+        }
+    }
+}
+
+fn next_random_helper(memory: &mut Memory) -> WordSize {
+    (next_random_state(memory) >> 17) as WordSize
+}
+
+fn next_random_state(memory: &mut Memory) -> u32 {
+    let mut state = memory.random_state;
+    state ^= state << 13;
+    state ^= state >> 17;
+    state ^= state << 5;
+    memory.random_state = state;
+    state
+}
+
+/**
+ * returns: ms on the virtual clock since the program started, wrapping around every 65536 ms
+ */
+pub fn time(memory: &mut Memory, args: WordSize) -> WordSize {
+    assert!(args == 0);
+    memory.clock as u64 as WordSize
+}
+
+/**
+ * returns: number of times the host advanced the clock, usually once per frame, wrapping around
+ */
+pub fn ticks(memory: &mut Memory, args: WordSize) -> WordSize {
+    assert!(args == 0);
+    memory.ticks as WordSize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::random_range_helper;
+    use crate::display::DisplayTarget;
+    use crate::memory::{Memory, WordSize, DISPLAY_HEIGHT, DISPLAY_WIDTH, WORDSIZE};
+    use crate::program::Program;
+
+    /**
+     * Wraps the commands in a Main.main that returns once they are done
+     */
+    fn run_main(commands: &str) -> Program {
+        Program::run_headless(&format!(
+            "function Main.main 0\n{}\npush constant 0\nreturn\n",
+            commands
+        ))
+    }
+
+    fn pixel(memory: &Memory, x: WordSize, y: WordSize) -> bool {
+        let index = y * (DISPLAY_WIDTH / WORDSIZE) + x / WORDSIZE;
+        let word = memory.display_words()[index as usize];
+        (word >> (x % WORDSIZE)) & 1 == 1
+    }
+
+    /**
+     * Calls the function with the arguments, pushing negative numbers the way the compiler does
+     */
+    fn call(function: &str, args: &[WordSize]) -> String {
+        push_args(args) + &format!("call {} {}\npop temp 0", function, args.len())
+    }
+
+    fn push_args(args: &[WordSize]) -> String {
+        let mut commands = String::new();
+        for arg in args {
+            commands += &format!("push constant {}\n", arg.unsigned_abs());
+            if *arg < 0 {
+                commands += "neg\n";
+            }
+        }
+        commands
+    }
+
+    fn error_code(function: &str, args: &[WordSize]) -> Option<WordSize> {
+        run_main(&call(function, args)).error_code()
+    }
+
+    #[test]
+    fn draw_pixel_off_the_display_is_error_7() {
+        assert_eq!(error_code("Screen.drawPixel", &[511, 255]), None);
+        assert_eq!(error_code("Screen.drawPixel", &[512, 0]), Some(7));
+        assert_eq!(error_code("Screen.drawPixel", &[0, 256]), Some(7));
+        assert_eq!(error_code("Screen.drawPixel", &[-1, 0]), Some(7));
+    }
+
+    #[test]
+    fn draw_line_off_the_display_is_error_8() {
+        assert_eq!(error_code("Screen.drawLine", &[0, 0, 511, 255]), None);
+        assert_eq!(error_code("Screen.drawLine", &[0, 0, 512, 10]), Some(8));
+        assert_eq!(error_code("Screen.drawLine", &[0, -1, 10, 10]), Some(8));
+    }
+
+    #[test]
+    fn bad_rectangle_corners_are_error_9() {
+        for function in ["Screen.drawRectangle", "Screen.drawRectangleOutline"] {
+            assert_eq!(error_code(function, &[10, 10, 10, 10]), None);
+            assert_eq!(error_code(function, &[0, 0, 511, 255]), None);
+            // the first corner has to be the top left one
+            assert_eq!(error_code(function, &[20, 10, 10, 20]), Some(9));
+            assert_eq!(error_code(function, &[10, 20, 20, 10]), Some(9));
+            assert_eq!(error_code(function, &[0, 0, 512, 10]), Some(9));
+            assert_eq!(error_code(function, &[-5, 0, 10, 10]), Some(9));
+        }
+    }
+
+    #[test]
+    fn bad_circles_are_errors_12_and_13() {
+        assert_eq!(error_code("Screen.drawCircle", &[256, 128, 127]), None);
+        assert_eq!(error_code("Screen.drawCircle", &[10, 10, 0]), None);
+        assert_eq!(error_code("Screen.drawCircle", &[512, 128, 10]), Some(12));
+        assert_eq!(error_code("Screen.drawCircle", &[10, -1, 10]), Some(12));
+        assert_eq!(error_code("Screen.drawCircle", &[256, 128, 128]), Some(13));
+        assert_eq!(error_code("Screen.drawCircle", &[256, 128, -1]), Some(13));
+    }
+
+    #[test]
+    fn circles_must_fit_on_the_display() {
+        // each side of the display, just past it and just touching it
+        for (x, y) in [(9, 100), (502, 100), (100, 9), (100, 246)] {
+            assert_eq!(error_code("Screen.drawCircle", &[x, y, 10]), Some(13));
+        }
+        let program = run_main(&call("Screen.drawCircle", &[501, 245, 10]));
+        assert_eq!(program.error_code(), None);
+        let memory = program.memory();
+        assert!(pixel(memory, 511, 245) && pixel(memory, 491, 245) && pixel(memory, 501, 255));
+        assert!(!pixel(memory, 490, 245) && !pixel(memory, 511, 255));
+        // nothing wraps around onto the other side of the display
+        assert!(!pixel(memory, 0, 245) && !pixel(memory, 501, 0));
+    }
+
+    #[test]
+    fn pixels_off_the_display_are_clipped() {
+        let mut memory = Memory::new(256, 256, 256, 0, 0, DisplayTarget::Headless);
+        memory.screen_color = 1;
+        for (x, y) in [(-1, 0), (512, 0), (0, -1), (0, 256), (-1, 256), (600, 300)] {
+            memory.set_display_xy(x, y);
+        }
+        assert!(memory.display_words().iter().all(|word| *word == 0));
+        memory.set_display_xy(DISPLAY_WIDTH - 1, DISPLAY_HEIGHT - 1);
+        assert!(pixel(&memory, DISPLAY_WIDTH - 1, DISPLAY_HEIGHT - 1));
+    }
+
+    #[test]
+    fn rectangles_fill_both_corners() {
+        let program = run_main(&call("Screen.drawRectangle", &[10, 20, 30, 40]));
+        let memory = program.memory();
+        assert!(pixel(memory, 10, 20) && pixel(memory, 30, 40) && pixel(memory, 20, 30));
+        assert!(!pixel(memory, 9, 20) && !pixel(memory, 31, 40) && !pixel(memory, 10, 41));
+    }
+
+    #[test]
+    fn string_addresses_past_the_word_fault_instead_of_wrapping() {
+        // a string at the top of RAM claiming to hold 32767 characters
+        let length = "push constant 16383\npop pointer 1\npush constant 32767\npop that 0\n";
+        let calls: [(&str, &[WordSize]); 2] = [
+            ("String.charAt", &[16383, 32766]),
+            ("String.setCharAt", &[16383, 32766, 65]),
+        ];
+        for (function, args) in calls {
+            let program = run_main(&(length.to_string() + &call(function, args)));
+            assert_eq!(
+                program.fault(),
+                Some("Invalid memory index: 16383 + 32767".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn string_test_prints_the_expected_output() {
+        let program = Program::run_headless(include_str!("../testdata/StringTest/Main.vm"));
+        assert_eq!(program.fault(), None);
+        assert_eq!(program.error_code(), None);
+        assert_eq!(
+            program.text().trim_end(),
+            [
+                "new,appendChar: abcde",
+                "setInt: 12345",
+                "setInt: -32767",
+                "length: 5",
+                "charAt[2]: 99",
+                "setCharAt(2,'-'): ab-de",
+                "eraseLastChar: ab-d",
+                "intValue: 456",
+                "intValue: -32123",
+                "backSpace: 129",
+                "doubleQuote: 34",
+                "newLine: 128",
+            ]
+            .join("\n")
+        );
+    }
+
+    /**
+     * Puts a new string of the capacity holding the text in temp 1
+     */
+    fn new_string(capacity: WordSize, text: &str) -> String {
+        let mut commands = format!(
+            "push constant {}\ncall String.new 1\npop temp 1\n",
+            capacity
+        );
+        for character in text.bytes() {
+            commands += &format!(
+                "push temp 1\npush constant {}\ncall String.appendChar 2\npop temp 0\n",
+                character
+            );
+        }
+        commands
+    }
+
+    /**
+     * Calls the String method on a new string of the capacity holding the text
+     */
+    fn string_method_error_code(
+        capacity: WordSize,
+        text: &str,
+        method: &str,
+        args: &[WordSize],
+    ) -> Option<WordSize> {
+        let mut commands = new_string(capacity, text);
+        commands += "push temp 1\n";
+        commands += &push_args(args);
+        commands += &format!("call {} {}\npop temp 0", method, args.len() + 1);
+        run_main(&commands).error_code()
+    }
+
+    #[test]
+    fn string_new_with_a_negative_length_raises_error_14() {
+        assert_eq!(error_code("String.new", &[-1]), Some(14));
+        assert_eq!(error_code("String.new", &[0]), None);
+    }
+
+    #[test]
+    fn char_at_outside_the_string_raises_error_15() {
+        assert_eq!(
+            string_method_error_code(4, "ab", "String.charAt", &[2]),
+            Some(15)
+        );
+        assert_eq!(
+            string_method_error_code(4, "ab", "String.charAt", &[-1]),
+            Some(15)
+        );
+        assert_eq!(
+            string_method_error_code(4, "ab", "String.charAt", &[1]),
+            None
+        );
+    }
+
+    #[test]
+    fn set_char_at_outside_the_string_raises_error_16() {
+        let set_char_at =
+            |index| string_method_error_code(4, "ab", "String.setCharAt", &[index, 45]);
+        assert_eq!(set_char_at(2), Some(16));
+        assert_eq!(set_char_at(-1), Some(16));
+        assert_eq!(set_char_at(1), None);
+    }
+
+    #[test]
+    fn append_char_to_a_full_string_raises_error_17() {
+        assert_eq!(
+            string_method_error_code(2, "ab", "String.appendChar", &[99]),
+            Some(17)
+        );
+        assert_eq!(
+            string_method_error_code(0, "", "String.appendChar", &[99]),
+            Some(17)
+        );
+        assert_eq!(
+            string_method_error_code(3, "ab", "String.appendChar", &[99]),
+            None
+        );
+    }
+
+    #[test]
+    fn erase_last_char_of_an_empty_string_raises_error_18() {
+        assert_eq!(
+            string_method_error_code(2, "", "String.eraseLastChar", &[]),
+            Some(18)
+        );
+        assert_eq!(
+            string_method_error_code(2, "a", "String.eraseLastChar", &[]),
+            None
+        );
+    }
+
+    #[test]
+    fn set_int_beyond_the_capacity_raises_error_19() {
+        assert_eq!(
+            string_method_error_code(2, "", "String.setInt", &[123]),
+            Some(19)
+        );
+        assert_eq!(
+            string_method_error_code(2, "", "String.setInt", &[-10]),
+            Some(19)
+        );
+        assert_eq!(
+            string_method_error_code(3, "", "String.setInt", &[-10]),
+            None
+        );
+    }
+
+    #[test]
+    fn random_range_covers_the_whole_word() {
+        let mut memory = Memory::new(256, 256, 256, 0, 0, DisplayTarget::Headless);
+        let draws: Vec<WordSize> = (0..1000)
+            .map(|_| random_range_helper(&mut memory, WordSize::MIN, WordSize::MAX))
+            .collect();
+        assert!(draws.iter().any(|&draw| draw < -16384));
+        assert!(draws.iter().any(|&draw| draw > 16384));
+        assert_eq!(random_range_helper(&mut memory, 5, 4), 5);
+        assert_eq!(random_range_helper(&mut memory, -3, -3), -3);
+    }
+
+    #[test]
+    fn random_range_is_not_biased_to_low_numbers() {
+        // 20000 doesn't divide the random draws, so a plain modulo would favour the low half
+        let mut memory = Memory::new(256, 256, 256, 0, 0, DisplayTarget::Headless);
+        let low_half = (0..20000)
+            .filter(|_| random_range_helper(&mut memory, 0, 19999) < 10000)
+            .count();
+        assert!(
+            (9700..10300).contains(&low_half),
+            "{} draws in the low half",
+            low_half
+        );
+    }
+
+    #[test]
+    fn storage_round_trips_an_array() {
+        let program = run_main(&format!(
+            "{}push constant 3000\npop pointer 1\npush constant 7\npop that 0\n\
+             push constant 9\nneg\npop that 1\n\
+             push temp 1\npush constant 3000\npush constant 2\ncall Storage.write 3\npop temp 0\n\
+             push temp 1\npush constant 4000\ncall Storage.read 2\npop temp 2",
+            new_string(2, "hi")
+        ));
+        assert_eq!(program.fault(), None);
+        let memory = program.memory();
+        assert_eq!(memory.read(4000), Some(7));
+        assert_eq!(memory.read(4001), Some(-9));
+        assert_eq!(memory.read(7), Some(2));
+    }
+
+    #[test]
+    fn storage_write_past_the_memory_map_faults_and_saves_nothing() {
+        let program = run_main(&format!(
+            "{}push temp 1\npush constant 24580\npush constant 3\ncall Storage.write 3\npop temp 0",
+            new_string(1, "k")
+        ));
+        assert_eq!(
+            program.fault(),
+            Some("Invalid memory index: 24581".to_string())
+        );
+        // the export holds the entry count after the magic and version
+        assert_eq!(program.export_storage()[5..9], [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn memory_alloc_of_a_negative_size_raises_error_5() {
+        assert_eq!(error_code("Memory.alloc", &[-1]), Some(5));
+        assert_eq!(error_code("Memory.alloc", &[0]), None);
+        assert_eq!(error_code("Memory.alloc", &[1]), None);
+    }
+
+    /**
+     * Runs the commands in Main.main with the keys the host queues before it starts
+     */
+    fn run_main_with_keys(commands: &str, keys: impl FnOnce(&mut Program)) -> Program {
+        let mut program = Program::with_display_target(
+            &format!(
+                "function Main.main 0\n{}\npush constant 0\nreturn\n",
+                commands
+            ),
+            DisplayTarget::Headless,
+        );
+        keys(&mut program);
+        program.run(1_000_000);
+        assert!(program.finished, "test program didn't finish");
+        program
+    }
+
+    fn press(program: &mut Program, code: WordSize) {
+        program.key_down(code);
+        program.key_up(code);
+    }
+
+    #[test]
+    fn read_char_returns_and_echoes_the_released_key() {
+        let read_char = "call Keyboard.readChar 0\npop static 0\n\
+                         call Keyboard.readChar 0\npop static 1";
+        let program = run_main_with_keys(read_char, |program| {
+            program.type_text("x");
+            // arrows have nothing to echo
+            press(program, 130);
+        });
+        let memory = program.memory();
+        assert_eq!((memory.read(15), memory.read(16)), (Some(120), Some(130)));
+        assert_eq!(program.text().trim_end(), "x");
+    }
+
+    #[test]
+    fn a_key_counts_once_it_is_released() {
+        let mut program = Program::with_display_target(
+            "function Main.main 0\ncall Keyboard.readChar 0\npop static 0\npush constant 0\nreturn\n",
+            DisplayTarget::Headless,
+        );
+        program.run(1000);
+        assert!(program.waiting_for_key() && !program.finished);
+        program.key_down(65);
+        program.run(1000);
+        assert!(program.waiting_for_key());
+        assert_eq!(program.text().trim_end(), "");
+        program.key_up(65);
+        program.run(1000);
+        assert!(program.finished);
+        assert_eq!(program.memory().read(15), Some(65));
+        assert_eq!(program.text().trim_end(), "A");
+    }
+
+    #[test]
+    fn read_line_echoes_the_line_and_backspace_stops_at_the_prompt() {
+        let read_line = new_string(6, "name? ")
+            + "push temp 1\ncall Keyboard.readLine 1\npop static 0\n\
+               push static 0\ncall Output.printString 1\npop temp 0";
+        let program = run_main_with_keys(&read_line, |program| {
+            program.type_text("abc");
+            for _ in 0..5 {
+                press(program, 129);
+            }
+            program.type_text("xy\n");
+        });
+        assert_eq!(program.fault(), None);
+        // the line ends with a new line, then the returned string is printed
+        assert_eq!(program.text().trim_end(), "name? xy\nxy");
+        let line = program.memory().read(15).unwrap();
+        assert_eq!(program.memory().read(line), Some(2));
+    }
+
+    #[test]
+    fn read_int_parses_the_line_until_the_first_non_digit() {
+        let read_int = new_string(3, "n? ")
+            + "push temp 1\ncall Keyboard.readInt 1\npop static 0\n\
+               push temp 1\ncall Keyboard.readInt 1\npop static 1\n\
+               push static 0\ncall Output.printInt 1\npop temp 0";
+        let program = run_main_with_keys(&read_int, |program| {
+            program.type_text("-41");
+            press(program, 129);
+            program.type_text("2\n12a3\n");
+        });
+        let memory = program.memory();
+        assert_eq!((memory.read(15), memory.read(16)), (Some(-42), Some(12)));
+        assert_eq!(program.text().trim_end(), "n? -42\nn? 12a3\n-42");
+    }
+}
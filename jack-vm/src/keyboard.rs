@@ -0,0 +1,322 @@
+use std::collections::VecDeque;
+use wasm_bindgen::prelude::*;
+
+use crate::jacklib::NEWLINE;
+use crate::memory::WordSize;
+
+// Hack codes of the keys that aren't printable characters, by their DOM KeyboardEvent.key name
+const SPECIAL_KEYS: [(&str, WordSize); 25] = [
+    ("Enter", 128),
+    ("Backspace", 129),
+    ("ArrowLeft", 130),
+    ("ArrowUp", 131),
+    ("ArrowRight", 132),
+    ("ArrowDown", 133),
+    ("Home", 134),
+    ("End", 135),
+    ("PageUp", 136),
+    ("PageDown", 137),
+    ("Insert", 138),
+    ("Delete", 139),
+    ("Escape", 140),
+    ("F1", 141),
+    ("F2", 142),
+    ("F3", 143),
+    ("F4", 144),
+    ("F5", 145),
+    ("F6", 146),
+    ("F7", 147),
+    ("F8", 148),
+    ("F9", 149),
+    ("F10", 150),
+    ("F11", 151),
+    ("F12", 152),
+];
+
+/**
+ * Converts a DOM KeyboardEvent.key name into the Hack keyboard code of the key
+ * Printable ASCII characters map to themselves, special keys to 128-152.
+ * Returns 0, the code for no key, for keys the Hack keyboard doesn't have
+ */
+#[wasm_bindgen]
+pub fn hack_key_code(key: &str) -> WordSize {
+    let mut characters = key.chars();
+    match (characters.next(), characters.next()) {
+        (Some(character), None) => char_code(character),
+        _ => SPECIAL_KEYS
+            .iter()
+            .find(|(name, _)| *name == key)
+            .map_or(0, |(_, code)| *code),
+    }
+}
+
+fn char_code(character: char) -> WordSize {
+    match character {
+        ' '..='~' => character as WordSize,
+        _ => 0,
+    }
+}
+
+enum KeyEvent {
+    Down(WordSize),
+    Up(WordSize),
+    UpAll,
+}
+
+/**
+ * Key presses and releases from the host, waiting to reach the keyboard register
+ * Each change of the register is held for a minimum number of steps before the next
+ * event is applied, so a press and release that arrive between two frames is still seen
+ * by a program polling Keyboard.keyPressed. While several keys are down the register
+ * holds the most recently pressed one.
+ */
+pub struct KeyQueue {
+    pending: VecDeque<KeyEvent>,
+    held: Vec<WordSize>,
+    current: WordSize,
+    shown_for: u32,
+}
+
+impl KeyQueue {
+    pub fn new() -> KeyQueue {
+        KeyQueue {
+            pending: VecDeque::new(),
+            held: Vec::new(),
+            current: 0,
+            // no key has been shown yet, so the first event applies straight away
+            shown_for: u32::MAX,
+        }
+    }
+
+    pub fn key_down(&mut self, code: WordSize) {
+        if code != 0 {
+            self.pending.push_back(KeyEvent::Down(code));
+        }
+    }
+
+    pub fn key_up(&mut self, code: WordSize) {
+        if code != 0 {
+            self.pending.push_back(KeyEvent::Up(code));
+        }
+    }
+
+    /**
+     * Queues the release of every key, e.g. when the host stops seeing key events
+     */
+    pub fn release_all(&mut self) {
+        self.pending.push_back(KeyEvent::UpAll);
+    }
+
+    /**
+     * Queues a press and a release for every character of the text
+     * Line breaks press Enter, characters the Hack keyboard doesn't have are skipped
+     */
+    pub fn type_text(&mut self, text: &str) {
+        for character in text.chars() {
+            let code = match character {
+                '\n' => NEWLINE,
+                _ => char_code(character),
+            };
+            self.key_down(code);
+            self.key_up(code);
+        }
+    }
+
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /**
+     * Returns the keyboard register for the next step, applying queued events
+     * once the current value has been seen for at least min_steps steps
+     */
+    pub fn next_key(&mut self, min_steps: u32) -> WordSize {
+        while self.shown_for >= min_steps {
+            let Some(event) = self.pending.pop_front() else {
+                break;
+            };
+            match event {
+                KeyEvent::Down(code) => {
+                    self.held.retain(|&held| held != code);
+                    self.held.push(code);
+                }
+                KeyEvent::Up(code) => self.held.retain(|&held| held != code),
+                KeyEvent::UpAll => self.held.clear(),
+            }
+            let key = self.held.last().copied().unwrap_or(0);
+            if key != self.current {
+                self.current = key;
+                self.shown_for = 0;
+            }
+        }
+        self.shown_for = self.shown_for.saturating_add(1);
+        self.current
+    }
+}
+
+// Hack key codes are below 256
+const KEY_CODES: usize = 256;
+
+/**
+ * Every key the host holds down, for reading several keys at once, as in a game that moves and fires
+ * Presses and releases are also latched until the program asks about them,
+ * so a tap between two checks is never lost
+ */
+pub struct KeyBitmap {
+    down: [bool; KEY_CODES],
+    pressed: [bool; KEY_CODES],
+    released: [bool; KEY_CODES],
+}
+
+impl KeyBitmap {
+    pub fn new() -> KeyBitmap {
+        KeyBitmap {
+            down: [false; KEY_CODES],
+            pressed: [false; KEY_CODES],
+            released: [false; KEY_CODES],
+        }
+    }
+
+    pub fn press(&mut self, code: WordSize) {
+        if let Some(index) = key_index(code) {
+            if !self.down[index] {
+                self.down[index] = true;
+                self.pressed[index] = true;
+            }
+        }
+    }
+
+    pub fn release(&mut self, code: WordSize) {
+        if let Some(index) = key_index(code) {
+            if self.down[index] {
+                self.down[index] = false;
+                self.released[index] = true;
+            }
+        }
+    }
+
+    /**
+     * Releases every key that is down, latching each of them as released
+     */
+    pub fn release_all(&mut self) {
+        for (down, released) in self.down.iter_mut().zip(self.released.iter_mut()) {
+            if std::mem::take(down) {
+                *released = true;
+            }
+        }
+    }
+
+    pub fn is_down(&self, code: WordSize) -> bool {
+        key_index(code).is_some_and(|index| self.down[index])
+    }
+
+    /**
+     * Returns whether the key was pressed since the last time this was asked for it
+     */
+    pub fn take_pressed(&mut self, code: WordSize) -> bool {
+        key_index(code).is_some_and(|index| std::mem::take(&mut self.pressed[index]))
+    }
+
+    /**
+     * Returns whether the key was released since the last time this was asked for it
+     */
+    pub fn take_released(&mut self, code: WordSize) -> bool {
+        key_index(code).is_some_and(|index| std::mem::take(&mut self.released[index]))
+    }
+}
+
+fn key_index(code: WordSize) -> Option<usize> {
+    usize::try_from(code)
+        .ok()
+        .filter(|&index| index > 0 && index < KEY_CODES)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * Returns the keyboard register for each of the steps
+     */
+    fn run(keys: &mut KeyQueue, steps: usize, min_steps: u32) -> Vec<WordSize> {
+        (0..steps).map(|_| keys.next_key(min_steps)).collect()
+    }
+
+    #[test]
+    fn hack_key_codes() {
+        assert_eq!(hack_key_code("a"), 97);
+        assert_eq!(hack_key_code("!"), 33);
+        assert_eq!(hack_key_code("Enter"), 128);
+        assert_eq!(hack_key_code("F12"), 152);
+        assert_eq!(hack_key_code("Shift"), 0);
+        assert_eq!(hack_key_code("é"), 0);
+    }
+
+    #[test]
+    fn the_first_key_shows_straight_away() {
+        let mut keys = KeyQueue::new();
+        keys.key_down(65);
+        assert_eq!(run(&mut keys, 1, 3), [65]);
+    }
+
+    #[test]
+    fn short_presses_are_held_for_the_minimum_steps() {
+        let mut keys = KeyQueue::new();
+        keys.key_down(65);
+        keys.key_up(65);
+        keys.key_down(66);
+        assert_eq!(run(&mut keys, 7, 3), [65, 65, 65, 0, 0, 0, 66]);
+    }
+
+    #[test]
+    fn the_last_key_pressed_shows_until_it_is_released() {
+        let mut keys = KeyQueue::new();
+        keys.key_down(65);
+        keys.key_down(66);
+        keys.key_up(66);
+        assert_eq!(run(&mut keys, 3, 1), [65, 66, 65]);
+    }
+
+    #[test]
+    fn release_all_clears_every_held_key() {
+        let mut keys = KeyQueue::new();
+        keys.key_down(65);
+        keys.key_down(66);
+        keys.release_all();
+        assert_eq!(run(&mut keys, 3, 1), [65, 66, 0]);
+        assert!(!keys.has_pending());
+    }
+
+    #[test]
+    fn the_bitmap_latches_presses_and_releases_until_taken() {
+        let mut keys = KeyBitmap::new();
+        keys.press(65);
+        keys.release(65);
+        assert!(!keys.is_down(65));
+        assert!(keys.take_pressed(65));
+        assert!(!keys.take_pressed(65));
+        assert!(keys.take_released(65));
+        assert!(!keys.take_released(65));
+    }
+
+    #[test]
+    fn the_bitmap_ignores_codes_outside_the_keyboard() {
+        let mut keys = KeyBitmap::new();
+        for code in [0, -1, 256] {
+            keys.press(code);
+            assert!(!keys.is_down(code));
+            assert!(!keys.take_pressed(code));
+        }
+    }
+
+    #[test]
+    fn release_all_lets_go_of_every_key_down() {
+        let mut keys = KeyBitmap::new();
+        keys.press(33);
+        keys.press(130);
+        keys.release_all();
+        assert!(!keys.is_down(33) && !keys.is_down(130));
+        assert!(keys.take_released(33) && keys.take_released(130));
+        assert!(!keys.take_released(49));
+    }
+}
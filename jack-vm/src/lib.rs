@@ -4,6 +4,16 @@ mod program;
 mod utils;
 mod jacklib;
 mod charmap;
+mod cycles;
+mod display;
+mod gamepad;
+mod image;
+mod keyboard;
+mod mouse;
+mod recorder;
+mod sound;
+mod storage;
+mod text;
 
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_test::console_log;
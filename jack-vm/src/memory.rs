@@ -1,9 +1,14 @@
-use wasm_bindgen::JsCast;
-use web_sys::CanvasRenderingContext2d;
-
 use crate::{
     charmap::CharMap,
+    display::{DirtyRegion, DisplayTarget, Palette},
+    gamepad::Gamepad,
+    jacklib::KeyRead,
+    keyboard::KeyBitmap,
+    mouse::{Mouse, MOUSE_MAX_INDEX, MOUSE_MEM},
     parser::{Offset, Segment},
+    sound::Voice,
+    storage::Storage,
+    text::{TextGrid, CHAR_HEIGHT, LINES},
 };
 use std::{
     ops::{Index, IndexMut},
@@ -31,16 +36,16 @@ const STATIC: WordSize = 15;
 const STATIC_MAX: WordSize = 255;
 const TEMP: WordSize = 5;
 const TEMP_MAX: WordSize = 12;
-// Display canvas constants
-pub const FILL_COLOR: &str = "rgb(0, 255, 0)";
-pub const EMPTY_COLOR: &str = "rgb(10, 10, 10)";
-pub const FILL_COLOR_ARR: [u8; 4] = [0, 255, 0, 255];
-pub const EMPTY_COLOR_ARR: [u8; 4] = [10, 10, 10, 255];
+// The heap grows down from the top of ram until it would reach the stack
+const HEAP_BASE: WordSize = 2048;
+// Programs that never call Random.seed get the same numbers on every run
+pub(crate) const RANDOM_SEED: u32 = 0x2545_F491;
 
 struct HeapAllocation {
     pointer: WordSize,
     size: WordSize,
     status: MemoryStatus,
+    class_name: Option<String>,
 }
 
 #[derive(PartialEq, Eq)]
@@ -55,6 +60,7 @@ impl HeapAllocation {
             pointer,
             size,
             status: MemoryStatus::Used,
+            class_name: None,
         }
     }
 
@@ -67,6 +73,7 @@ impl HeapAllocation {
             pointer: new_pointer,
             size: new_size,
             status: MemoryStatus::Free,
+            class_name: None,
         }
     }
 }
@@ -83,16 +90,32 @@ impl HeapAllocation {
 pub struct Memory {
     ram: MemoryVec,
     display: MemoryVec,
-    pub canvas: web_sys::HtmlCanvasElement,
-    pub canvas_context: CanvasRenderingContext2d,
+    framebuffer: Vec<u8>,
+    dirty: Option<DirtyRegion>,
+    display_target: DisplayTarget,
+    palette: Palette,
     pub keyboard: WordSize,
     pub cursor_line: WordSize,
     pub cursor_col: WordSize,
+    pub output_scrolling: bool,
     pub screen_color: WordSize,
     pub char_map: CharMap,
+    pub text: TextGrid,
     heap_alloc: Vec<HeapAllocation>,
     pub display_updated: bool,
     pub finished: bool,
+    pub fault: Option<String>,
+    pub error_code: Option<WordSize>,
+    pub wait_request: Option<WordSize>,
+    pub clock: f64,
+    pub ticks: u64,
+    pub random_state: u32,
+    pub key_read: Option<KeyRead>,
+    pub keys: KeyBitmap,
+    pub mouse: Mouse,
+    pub gamepad: Gamepad,
+    pub voice: Voice,
+    pub storage: Storage,
 }
 
 struct MemoryVec(Vec<WordSize>);
@@ -131,6 +154,7 @@ impl Memory {
         arg: WordSize,
         this: WordSize,
         that: WordSize,
+        display_target: DisplayTarget,
     ) -> Memory {
         let mut ram = MemoryVec::new(vec![0; Memory::ram_size() as usize]);
         let display = MemoryVec::new(vec![0; Memory::display_size() as usize]);
@@ -140,41 +164,37 @@ impl Memory {
         ram[THIS] = this;
         ram[THAT] = that;
 
-        // Initialize display canvas
-        let document = web_sys::window().unwrap().document().unwrap();
-        let canvas = document.get_element_by_id("display-canvas").unwrap();
-        let canvas: web_sys::HtmlCanvasElement = canvas
-            .dyn_into::<web_sys::HtmlCanvasElement>()
-            .map_err(|_| ())
-            .unwrap();
-
-        canvas.set_width(DISPLAY_WIDTH as u32);
-        canvas.set_height(DISPLAY_HEIGHT as u32);
-
-        let canvas_context = canvas
-            .get_context("2d")
-            .unwrap()
-            .unwrap()
-            .dyn_into::<web_sys::CanvasRenderingContext2d>()
-            .unwrap();
-
-        canvas_context.set_line_width(1.into());
-        canvas_context.set_fill_style(&FILL_COLOR.into());
-        canvas_context.set_stroke_style(&FILL_COLOR.into());
-
         Memory {
             ram,
             display,
-            canvas,
-            canvas_context,
+            framebuffer: Palette::GREEN
+                .word_pixels(0)
+                .repeat(Memory::display_size() as usize),
+            dirty: Some(DirtyRegion::full_display()),
+            display_target,
+            palette: Palette::GREEN,
             keyboard: 0,
             cursor_line: 0,
             cursor_col: 0,
+            output_scrolling: false,
             screen_color: 1,
             char_map: CharMap::new(),
+            text: TextGrid::new(),
             heap_alloc: Vec::new(),
             display_updated: false,
             finished: false,
+            fault: None,
+            error_code: None,
+            wait_request: None,
+            clock: 0.0,
+            ticks: 0,
+            random_state: RANDOM_SEED,
+            key_read: None,
+            keys: KeyBitmap::new(),
+            mouse: Mouse::new(),
+            gamepad: Gamepad::new(),
+            voice: Voice::Silent,
+            storage: Storage::new(),
         }
     }
 
@@ -183,27 +203,16 @@ impl Memory {
      */
     pub fn push(&mut self, segment: Segment, offset: Offset) {
         let value = match segment {
-            Segment::Pointer => {
-                if offset == 0 {
-                    self.get_pointer(THIS)
-                } else if offset == 1 {
-                    self.get_pointer(THAT)
-                } else {
-                    panic!("Pointer can only have offset of 0 or 1")
-                }
-            }
-            Segment::Constant => offset.to_owned(),
-            Segment::Local => self.get_value_by_pointer(LCL, offset),
-            Segment::Argument => self.get_value_by_pointer(ARG, offset),
-            Segment::Static => *self.peek(STATIC + offset),
-            Segment::This => self.get_value_by_pointer(THIS, offset),
-            Segment::That => self.get_value_by_pointer(THAT, offset),
-            Segment::Temp => *self.peek(TEMP + offset),
+            Segment::Constant => offset,
+            _ => match self.segment_address(segment, offset) {
+                Some(address) => self.peek(address),
+                None => 0,
+            },
         };
         let stack_pointer = self.get_pointer(SP);
         // Set value to stack and increment SP
-        self.ram[stack_pointer] = value;
-        self.ram[SP] += 1;
+        self.poke(stack_pointer, value);
+        self.ram[SP] = self.ram[SP].wrapping_add(1);
     }
 
     /**
@@ -212,43 +221,55 @@ impl Memory {
      */
     pub fn pop(&mut self, segment: Segment, offset: Offset) -> WordSize {
         // Decrement SP
-        self.ram[SP] -= 1;
+        self.ram[SP] = self.ram[SP].wrapping_sub(1);
         let value = self.get_value_by_pointer(SP, 0);
 
+        if segment == Segment::Constant {
+            self.raise_fault("Constant can only be pushed".into());
+        } else if let Some(address) = self.segment_address(segment, offset) {
+            // wasm_bindgen_test::console_log!("{}", format!("\t>>> Popping address {}", address));
+            self.poke(address, value);
+        }
+        value
+    }
+
+    /**
+     * Resolves a segment and offset to an address in the HACK memory map
+     * Raises a fault and returns None if the offset is outside of the segment
+     */
+    fn segment_address(&mut self, segment: Segment, offset: Offset) -> Option<WordSize> {
         let address = match segment {
-            Segment::Pointer => {
-                if offset == 0 {
-                    THIS
-                } else if offset == 1 {
-                    THAT
-                } else {
-                    panic!("Pointer can only have offset of 0 or 1")
+            Segment::Pointer => match offset {
+                0 => THIS,
+                1 => THAT,
+                _ => {
+                    self.raise_fault("Pointer can only have offset of 0 or 1".into());
+                    return None;
                 }
-            }
-            Segment::Constant => panic!("Constant can only be pushed"),
-            Segment::Local => self.get_pointer(LCL) + offset,
-            Segment::Argument => self.get_pointer(ARG) + offset,
+            },
+            Segment::Constant => panic!("Constant segment has no address"),
+            Segment::Local => self.get_pointer(LCL).wrapping_add(offset),
+            Segment::Argument => self.get_pointer(ARG).wrapping_add(offset),
             Segment::Static => {
-                if STATIC + offset <= STATIC_MAX {
+                if (0..=STATIC_MAX - STATIC).contains(&offset) {
                     STATIC + offset
                 } else {
-                    panic!("Static memory segment overflow.")
+                    self.raise_fault("Static memory segment overflow.".into());
+                    return None;
                 }
             }
-            Segment::This => self.get_pointer(THIS) + offset,
-            Segment::That => self.get_pointer(THAT) + offset,
+            Segment::This => self.get_pointer(THIS).wrapping_add(offset),
+            Segment::That => self.get_pointer(THAT).wrapping_add(offset),
             Segment::Temp => {
-                if TEMP + offset <= TEMP_MAX {
+                if (0..=TEMP_MAX - TEMP).contains(&offset) {
                     TEMP + offset
                 } else {
-                    panic!("Temp memory segment overflow.")
+                    self.raise_fault("Temp memory segment overflow.".into());
+                    return None;
                 }
             }
         };
-        // wasm_bindgen_test::console_log!("{}", format!("\t>>> Popping address {}", address));
-
-        self.ram[address] = value;
-        value
+        Some(address)
     }
 
     pub fn get_pointer(&self, pointer: WordSize) -> WordSize {
@@ -259,22 +280,39 @@ impl Memory {
         self.ram[pointer] = value;
     }
 
-    pub fn get_value_by_pointer(&self, pointer: WordSize, offset: WordSize) -> WordSize {
-        self.ram[self.ram[pointer] + offset]
+    pub fn get_value_by_pointer(&mut self, pointer: WordSize, offset: WordSize) -> WordSize {
+        self.peek(self.ram[pointer].wrapping_add(offset))
     }
 
     /**
-     * Returns a reference to the value of memory at the index, using the HACK computer memory mapping
+     * Returns the value of memory at the index, using the HACK computer memory mapping
      * ram: 0-16383
      * display: 16384-24575
      * keyboard: 24576
+     * mouse: 24577-24580, a web-jack extension, see Mouse
+     * Returns None for addresses outside of the memory map
      */
-    pub fn peek(&self, index: WordSize) -> &WordSize {
+    pub fn read(&self, index: WordSize) -> Option<WordSize> {
         match index {
-            0..=RAM_MAX_INDEX => &self.ram[index],
-            RAM_SIZE..=DISPLAY_MAX_INDEX => &self.display[index],
-            KEYBOARD_MEM => &self.keyboard,
-            _ => panic!("Invalid memory index: {}", index),
+            0..=RAM_MAX_INDEX => Some(self.ram[index]),
+            RAM_SIZE..=DISPLAY_MAX_INDEX => Some(self.display[index - RAM_SIZE]),
+            KEYBOARD_MEM => Some(self.keyboard),
+            MOUSE_MEM..=MOUSE_MAX_INDEX => Some(self.mouse.register(index)),
+            _ => None,
+        }
+    }
+
+    /**
+     * Returns the value of memory at the index, using the HACK computer memory mapping
+     * Raises a fault and returns 0 for addresses outside of the memory map
+     */
+    pub fn peek(&mut self, index: WordSize) -> WordSize {
+        match self.read(index) {
+            Some(value) => value,
+            None => {
+                self.raise_fault(format!("Invalid memory index: {}", index));
+                0
+            }
         }
     }
 
@@ -283,18 +321,60 @@ impl Memory {
      * ram: 0-16383
      * display: 16384-24575
      * keyboard: 24576
+     * mouse: 24577-24580
+     * Raises a fault for addresses outside of the memory map
      * Returns: Void
      */
     pub fn poke(&mut self, index: WordSize, value: WordSize) {
         match index {
             0..=RAM_MAX_INDEX => self.ram[index] = value,
-            RAM_SIZE..=DISPLAY_MAX_INDEX => self.display[index] = value,
+            RAM_SIZE..=DISPLAY_MAX_INDEX => self.set_display_word(index - RAM_SIZE, value),
             KEYBOARD_MEM => self.keyboard = value,
-            _ => panic!("Invalid memory index: {}", index),
+            MOUSE_MEM..=MOUSE_MAX_INDEX => self.mouse.set_register(index, value),
+            _ => self.raise_fault(format!("Invalid memory index: {}", index)),
         };
     }
 
-    pub fn get_arg(&self, arg_num: WordSize) -> WordSize {
+    /**
+     * Returns the value at an offset from an object pointer
+     * Raises a fault if the address overflows the word rather than wrapping back into memory
+     */
+    pub fn peek_offset(&mut self, pointer: WordSize, offset: WordSize) -> WordSize {
+        match pointer.checked_add(offset) {
+            Some(address) => self.peek(address),
+            None => {
+                self.raise_fault(format!("Invalid memory index: {} + {}", pointer, offset));
+                0
+            }
+        }
+    }
+
+    /**
+     * Changes the value at an offset from an object pointer
+     * Raises a fault if the address overflows the word rather than wrapping back into memory
+     */
+    pub fn poke_offset(&mut self, pointer: WordSize, offset: WordSize, value: WordSize) {
+        match pointer.checked_add(offset) {
+            Some(address) => self.poke(address, value),
+            None => self.raise_fault(format!("Invalid memory index: {} + {}", pointer, offset)),
+        }
+    }
+
+    /**
+     * Stops execution because of an invalid memory access
+     * Only the first fault is kept, since later ones are usually caused by it
+     */
+    pub fn raise_fault(&mut self, message: String) {
+        // console logging is only available in the browser, headless runs read the fault instead
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_test::console_log!("Fault: {}", message);
+        if self.fault.is_none() {
+            self.fault = Some(message);
+        }
+        self.finished = true;
+    }
+
+    pub fn get_arg(&mut self, arg_num: WordSize) -> WordSize {
         self.get_value_by_pointer(ARG, arg_num)
     }
 
@@ -307,7 +387,10 @@ impl Memory {
         self.push(Segment::Constant, self.get_pointer(THIS));
         self.push(Segment::Constant, self.get_pointer(THAT));
         // Set Local Pointer
-        self.set_pointer(ARG, self.get_pointer(SP) - num_args - 5);
+        self.set_pointer(
+            ARG,
+            self.get_pointer(SP).wrapping_sub(num_args).wrapping_sub(5),
+        );
         self.set_pointer(LCL, self.get_pointer(SP));
     }
 
@@ -315,7 +398,7 @@ impl Memory {
         // move return value to where it can be accessed by caller
         self.pop(Segment::Argument, 0);
         // reposition SP
-        self.set_pointer(SP, self.get_pointer(ARG) + 1);
+        self.set_pointer(SP, self.get_pointer(ARG).wrapping_add(1));
         // reset memory pointers based on call stack
         let that = self.get_value_by_pointer(LCL, -1);
         self.set_pointer(THAT, that);
@@ -341,22 +424,39 @@ impl Memory {
     }
 
     pub fn set_display_xy(&mut self, x: WordSize, y: WordSize) {
-        self.display_updated = true;
-        let display_word = y * (DISPLAY_WIDTH / 16) + x / 16;
-        let bit = x % 16;
+        // pixels off the display are clipped rather than wrapping onto another row
+        if !(0..DISPLAY_WIDTH).contains(&x) || !(0..DISPLAY_HEIGHT).contains(&y) {
+            return;
+        }
+        let display_word = y * (DISPLAY_WIDTH / WORDSIZE) + x / WORDSIZE;
+        let bit = x % WORDSIZE;
         let mask: WordSize = 1 << bit;
-        if self.screen_color == 0 {
+        let value = if self.screen_color == 0 {
             // and with inverse of mask
-            self.display[display_word] &= !mask;
+            self.display[display_word] & !mask
         } else {
             // or with mask
-            self.display[display_word] |= mask;
-        }
+            self.display[display_word] | mask
+        };
+        self.set_display_word(display_word, value);
     }
 
+    /**
+     * Every write to display memory goes through here, so the framebuffer always matches it
+     */
     pub fn set_display_word(&mut self, index: WordSize, value: WordSize) {
         self.display_updated = true;
+        let old = self.display[index];
+        if old == value {
+            return;
+        }
         self.display[index] = value;
+        self.text.display_word_changed(index, old, value);
+        self.update_framebuffer_word(index);
+    }
+
+    pub fn display_words(&self) -> &[WordSize] {
+        &self.display.0
     }
 
     pub fn get_display_value(&self, index: WordSize) -> WordSize {
@@ -368,18 +468,114 @@ impl Memory {
     }
 
     pub fn set_display(&mut self, value: WordSize, offset: WordSize) {
-        self.display_updated = true;
-        self.display[offset] = value;
+        self.set_display_word(offset, value);
     }
 
     pub fn clear_display(&mut self) {
         self.display_updated = true;
         self.display.fill(0);
+        self.text.clear();
+        self.update_framebuffer();
     }
 
     pub fn fill_display(&mut self) {
         self.display_updated = true;
         self.display.fill(-1);
+        self.text.clear();
+        self.update_framebuffer();
+    }
+
+    /**
+     * Moves the display up by one line of text, clearing the last text line
+     * The display rows below the last text line are left alone
+     */
+    pub fn scroll_text_up(&mut self) {
+        let words_per_row = (DISPLAY_WIDTH / WORDSIZE) as usize;
+        let moved = CHAR_HEIGHT as usize * words_per_row;
+        let end = (LINES * CHAR_HEIGHT) as usize * words_per_row;
+        self.display_updated = true;
+        self.display.0.copy_within(moved..end, 0);
+        self.display.0[end - moved..end].fill(0);
+        self.text.scroll_up();
+        self.update_framebuffer();
+    }
+
+    /**
+     * Returns a pointer to the RGBA framebuffer derived from display memory
+     */
+    pub fn framebuffer(&self) -> *const u8 {
+        self.framebuffer.as_ptr()
+    }
+
+    pub fn framebuffer_size() -> usize {
+        (DISPLAY_WIDTH as usize) * (DISPLAY_HEIGHT as usize) * 4
+    }
+
+    /**
+     * Blits the part of the framebuffer that changed since the last render onto the display target
+     * Returns true if anything was drawn
+     */
+    pub fn render(&mut self) -> bool {
+        let dirty = match self.dirty.take() {
+            Some(dirty) => dirty,
+            None => return false,
+        };
+        self.display_target.blit(&self.framebuffer, dirty);
+        true
+    }
+
+    /**
+     * Writes the 16 pixels of a display word into the framebuffer and marks them dirty
+     * Pixel (r, c) is the c%16 bit of the word, so the least significant bit is leftmost
+     */
+    fn update_framebuffer_word(&mut self, index: WordSize) {
+        let words_per_row = DISPLAY_WIDTH / WORDSIZE;
+        let start = index as usize * WORDSIZE as usize * 4;
+        let pixels = self.palette.word_pixels(self.display[index]);
+        self.framebuffer[start..start + pixels.len()].copy_from_slice(&pixels);
+
+        let left = (index % words_per_row) * WORDSIZE;
+        let top = index / words_per_row;
+        self.mark_dirty(DirtyRegion {
+            left,
+            top,
+            right: left + WORDSIZE,
+            bottom: top + 1,
+        });
+    }
+
+    /**
+     * Rebuilds the whole framebuffer from display memory
+     * The buffer is rewritten in place so pointers held by javascript stay valid
+     */
+    fn update_framebuffer(&mut self) {
+        for (pixels, word) in self
+            .framebuffer
+            .chunks_mut(WORDSIZE as usize * 4)
+            .zip(self.display.0.iter())
+        {
+            pixels.copy_from_slice(&self.palette.word_pixels(*word));
+        }
+        self.mark_dirty(DirtyRegion::full_display());
+    }
+
+    fn mark_dirty(&mut self, region: DirtyRegion) {
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => dirty.union(region),
+            None => region,
+        });
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /**
+     * Changes the display colors and redraws the whole framebuffer with them
+     */
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.update_framebuffer();
     }
 
     pub fn keyboard(&self) -> WordSize {
@@ -388,18 +584,19 @@ impl Memory {
 
     /**
      * Allocates a block of memory of at least 'size' words
-     * Returns the pointer to the block
+     * Returns the pointer to the block, or None if the heap is out of space
      */
-    pub(crate) fn alloc(&mut self, requested_size: WordSize) -> WordSize {
+    pub(crate) fn alloc(&mut self, requested_size: WordSize) -> Option<WordSize> {
         // Walk through the HeapAllocations and return the first one that is big enough
-        for allocation in self
+        if let Some(allocation) = self
             .heap_alloc
             .iter_mut()
-            .filter(|a| a.status == MemoryStatus::Free && a.size >= requested_size)
+            .find(|a| a.status == MemoryStatus::Free && a.size >= requested_size)
         {
             allocation.status = MemoryStatus::Used;
+            allocation.class_name = None;
             // console_log!("Alloc returning reused block with address of {}", allocation.pointer);
-            return allocation.pointer;
+            return Some(allocation.pointer);
         }
 
         // Otherwise make a new allocation
@@ -409,11 +606,13 @@ impl Memory {
             .last()
             .map(|a| a.pointer)
             .unwrap_or(RAM_SIZE - 1);
-        let new_pointer = next_free - requested_size;
+        let new_pointer = next_free
+            .checked_sub(requested_size)
+            .filter(|pointer| *pointer >= HEAP_BASE)?;
         self.heap_alloc
             .push(HeapAllocation::new(new_pointer, requested_size));
-        wasm_bindgen_test::console_log!("Alloc returning new block with address of {}",new_pointer);
-        new_pointer
+        // console_log!("Alloc returning new block with address of {}", new_pointer);
+        Some(new_pointer)
     }
 
     /**
@@ -429,4 +628,47 @@ impl Memory {
             None => (),
         }
     }
+
+    /**
+     * Records the class of the object stored in the heap block starting at 'pointer'
+     */
+    pub(crate) fn tag_heap_block(&mut self, pointer: WordSize, class_name: &str) {
+        if let Some(a) = self.heap_alloc.iter_mut().find(|a| a.pointer == pointer) {
+            a.class_name = Some(class_name.to_string());
+        }
+    }
+
+    /**
+     * Finds the allocated heap block containing 'address'
+     * Returns the block's pointer, size and class (if it was tagged by a constructor)
+     */
+    pub(crate) fn heap_block(
+        &self,
+        address: WordSize,
+    ) -> Option<(WordSize, WordSize, Option<&str>)> {
+        self.heap_alloc
+            .iter()
+            .filter(|a| a.status == MemoryStatus::Used)
+            .find(|a| a.pointer <= address && address < a.pointer + a.size)
+            .map(|a| (a.pointer, a.size, a.class_name.as_deref()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_stack_pointer_wraps_instead_of_overflowing() {
+        let mut memory = Memory::new(256, 256, 256, 0, 0, DisplayTarget::Headless);
+        memory.set_pointer(SP, WordSize::MAX);
+        memory.push(Segment::Constant, 1);
+        assert_eq!(memory.get_pointer(SP), WordSize::MIN);
+        assert_eq!(
+            memory.fault,
+            Some("Invalid memory index: 32767".to_string())
+        );
+        memory.pop(Segment::Temp, 0);
+        assert_eq!(memory.get_pointer(SP), WordSize::MAX);
+    }
 }
@@ -0,0 +1,84 @@
+use crate::memory::{WordSize, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+// The mouse registers sit right after the keyboard, outside the standard Hack memory map
+pub(crate) const MOUSE_MEM: WordSize = 24577;
+pub(crate) const MOUSE_MAX_INDEX: WordSize = MOUSE_MEM + 3;
+
+/**
+ * A pointer over the display, as reported by the host
+ * Its registers, in order from MOUSE_MEM, are:
+ * x: 0-511, the column of the pixel under the pointer
+ * y: 0-255, the row of the pixel under the pointer
+ * buttons: the buttons held down, 1 for the main button, 2 for the secondary and 4 for the middle one
+ * clicked: the buttons pressed since Mouse.clicked last read them
+ */
+pub struct Mouse {
+    x: WordSize,
+    y: WordSize,
+    buttons: WordSize,
+    clicked: WordSize,
+}
+
+impl Mouse {
+    pub fn new() -> Mouse {
+        Mouse {
+            x: 0,
+            y: 0,
+            buttons: 0,
+            clicked: 0,
+        }
+    }
+
+    /**
+     * Moves the pointer, keeping it on the display
+     */
+    pub fn move_to(&mut self, x: WordSize, y: WordSize) {
+        self.x = x.clamp(0, DISPLAY_WIDTH - 1);
+        self.y = y.clamp(0, DISPLAY_HEIGHT - 1);
+    }
+
+    /**
+     * Sets the buttons held down, latching every button that wasn't down before as clicked
+     */
+    pub fn set_buttons(&mut self, buttons: WordSize) {
+        self.clicked |= buttons & !self.buttons;
+        self.buttons = buttons;
+    }
+
+    /**
+     * Returns the buttons clicked since the last call
+     */
+    pub fn take_clicked(&mut self) -> WordSize {
+        std::mem::take(&mut self.clicked)
+    }
+
+    pub fn x(&self) -> WordSize {
+        self.x
+    }
+
+    pub fn y(&self) -> WordSize {
+        self.y
+    }
+
+    pub fn buttons(&self) -> WordSize {
+        self.buttons
+    }
+
+    pub fn register(&self, index: WordSize) -> WordSize {
+        match index - MOUSE_MEM {
+            0 => self.x,
+            1 => self.y,
+            2 => self.buttons,
+            _ => self.clicked,
+        }
+    }
+
+    pub fn set_register(&mut self, index: WordSize, value: WordSize) {
+        match index - MOUSE_MEM {
+            0 => self.x = value,
+            1 => self.y = value,
+            2 => self.buttons = value,
+            _ => self.clicked = value,
+        }
+    }
+}
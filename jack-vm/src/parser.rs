@@ -75,10 +75,30 @@ fn parse_segment(seg_name: &str) -> Segment {
 #[derive(Debug, Clone)]
 pub struct Bytecode {
     pub functions: HashMap<String, Rc<RefCell<Function>>>,
+    pub classes: HashMap<String, ClassLayout>,
+}
+
+/**
+ * Field layout of a class, read from the structured comments emitted by the compiler:
+ * // @class Square
+ * // @field int x
+ * Fields are listed in the order they are stored in the object, starting at this 0
+ */
+#[derive(Debug, Clone)]
+pub struct ClassLayout {
+    pub name: String,
+    pub fields: Vec<FieldLayout>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldLayout {
+    pub name: String,
+    pub field_type: Option<String>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Function {
+    pub name: String,
     pub start_line: usize,
     pub num_vars: WordSize,
     pub commands: Vec<VMCommand>,
@@ -95,23 +115,56 @@ pub(crate) fn parse_bytecode(text: &str) -> Bytecode {
     // Initialize program and functions to be empty
     let mut program = Bytecode {
         functions: HashMap::new(),
+        classes: HashMap::new(),
     };
 
     // Initialized to bring into scope
     let mut current_function: Option<Rc<RefCell<Function>>> = None;
+    let mut current_class: Option<String> = None;
 
-    for (line_num, line) in text
-        .lines()
-        .map(|line| {
-            // remove comments: delete any text between '//' and end of line'
-            if let Some(i) = line.find("//") {
-                &line[..i]
-            } else {
-                line
+    for (line_num, line) in text.lines().enumerate() {
+        // class layout metadata is the only comment that isn't discarded
+        if let Some(directive) = line.trim().strip_prefix("//") {
+            let directive_words: Vec<&str> = directive.split_whitespace().collect();
+            let field = match directive_words[..] {
+                ["@class", class_name] => {
+                    program.classes.insert(
+                        class_name.to_string(),
+                        ClassLayout {
+                            name: class_name.to_string(),
+                            fields: Vec::new(),
+                        },
+                    );
+                    current_class = Some(class_name.to_string());
+                    None
+                }
+                ["@field", field_type, field_name] => Some(FieldLayout {
+                    name: field_name.to_string(),
+                    field_type: Some(field_type.to_string()),
+                }),
+                ["@field", field_name] => Some(FieldLayout {
+                    name: field_name.to_string(),
+                    field_type: None,
+                }),
+                _ => None,
+            };
+            // like any other comment, a field outside of a class is ignored
+            if let (Some(field), Some(class)) = (
+                field,
+                current_class
+                    .as_ref()
+                    .and_then(|name| program.classes.get_mut(name)),
+            ) {
+                class.fields.push(field);
             }
-        })
-        .enumerate()
-    {
+        }
+
+        // remove comments: delete any text between '//' and end of line'
+        let line = match line.find("//") {
+            Some(i) => &line[..i],
+            None => line,
+        };
+
         if line.trim().is_empty() {
             continue;
         }
@@ -184,6 +237,7 @@ pub(crate) fn parse_bytecode(text: &str) -> Bytecode {
                     ("function", fn_name, var_count) => {
                         // Initialize a new function
                         let f = Rc::new(RefCell::new(Function {
+                            name: fn_name.to_string(),
                             start_line: line_num,
                             num_vars: var_count,
                             commands: Vec::new(),
@@ -226,3 +280,53 @@ pub(crate) fn parse_bytecode(text: &str) -> Bytecode {
     }
     program
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field_names(layout: &ClassLayout) -> Vec<(&str, Option<&str>)> {
+        layout
+            .fields
+            .iter()
+            .map(|field| (field.name.as_str(), field.field_type.as_deref()))
+            .collect()
+    }
+
+    #[test]
+    fn class_directives_describe_the_field_layout() {
+        let bytecode = parse_bytecode(
+            "// @class Square\n\
+             // @field int x\n\
+             //   @field   size\n\
+             // a comment that isn't a directive\n\
+             // @field boolean visible\n\
+             function Square.new 0\n\
+             push constant 0\n\
+             return\n\
+             // @class Empty\n",
+        );
+        assert_eq!(
+            field_names(&bytecode.classes["Square"]),
+            [
+                ("x", Some("int")),
+                ("size", None),
+                ("visible", Some("boolean"))
+            ]
+        );
+        assert!(bytecode.classes["Empty"].fields.is_empty());
+        assert!(bytecode.functions.contains_key("Square.new"));
+    }
+
+    #[test]
+    fn fields_outside_of_a_class_are_ignored() {
+        let bytecode = parse_bytecode(
+            "// @field int x\n\
+             function Main.main 0\n\
+             push constant 0\n\
+             return\n",
+        );
+        assert!(bytecode.classes.is_empty());
+        assert!(bytecode.functions.contains_key("Main.main"));
+    }
+}
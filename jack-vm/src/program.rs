@@ -2,11 +2,16 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 use wasm_bindgen::prelude::*;
-use wasm_bindgen_test::console_log;
 
+use crate::cycles;
+use crate::display::{DisplayTarget, Palette};
+use crate::image;
 use crate::jacklib::{self, NativeFunction, clear_screen};
-use crate::memory::{Memory, WordSize};
-use crate::parser::{parse_bytecode, Bytecode, Command, Function, Segment};
+use crate::keyboard::KeyQueue;
+use crate::memory::{Memory, WordSize, SP};
+use crate::parser::{parse_bytecode, Bytecode, ClassLayout, Command, Function, Segment};
+use crate::recorder::Recorder;
+use crate::sound::{self, SoundBuffer};
 
 struct StackFrame {
     function: Rc<RefCell<Function>>,
@@ -22,22 +27,49 @@ impl StackFrame {
     }
 }
 
+// Cycles earned by a throttled program are capped at this many ms worth,
+// so it doesn't race to catch up after the host stops advancing the clock for a while
+const MAX_BUDGET_MS: f64 = 100.0;
+
+// Steps a key press or release is held in the keyboard register before the next queued one
+const KEY_HOLD_STEPS: u32 = 200;
+
 #[wasm_bindgen]
 pub struct Program {
     code: Bytecode,
     native_functions: HashMap<String, NativeFunction>,
     memory: Memory,
     call_stack: Vec<StackFrame>,
+    steps: u64,
+    recorder: Option<Recorder>,
+    stack_trace: Vec<String>,
+    sleeping_until: Option<f64>,
+    cycles: u64,
+    clock_rate: f64,
+    cycle_budget: f64,
+    strict: bool,
+    keys: KeyQueue,
+    sound: SoundBuffer,
     pub finished: bool,
 }
 
 #[wasm_bindgen]
 impl Program {
     /**
-     * Initializes the program given a set of code and a configuration
+     * Initializes the program given a set of code and a display target.
+     * The target can be a canvas, an OffscreenCanvas, one of their 2d contexts,
+     * a function called with each rendered frame, or undefined to run headless
      */
     #[wasm_bindgen(constructor)]
-    pub fn new(input: &str) -> Program {
+    pub fn new(input: &str, display_target: JsValue) -> Program {
+        Program::with_display_target(input, DisplayTarget::from_js(display_target))
+    }
+
+    /**
+     * Initializes the program given a set of code and a display target
+     * Tests pass DisplayTarget::Headless to run without a browser
+     */
+    pub(crate) fn with_display_target(input: &str, display_target: DisplayTarget) -> Program {
         // set panic hook
         console_error_panic_hook::set_once();
 
@@ -47,199 +79,12 @@ impl Program {
         let arg = 400;
         let this = 0;
         let that = 0;
-        let mut memory = Memory::new(sp, lcl, arg, this, that);
+        let mut memory = Memory::new(sp, lcl, arg, this, that, display_target);
 
-        // Fill canvas at init
+        // Clear display at init
         clear_screen(&mut memory, 0);
 
-        // some library functions are implemented in jack
-        // Keyboard.readChar
-        // Keyboard.readLine
-        // Keyboard.readInt
-        let jack_library_functions = String::from("function Keyboard.readChar 2
-            call Keyboard.keyPressed 0
-            pop local 1
-            push local 1
-            pop local 0
-            push local 1
-            push constant 0
-            eq
-            not
-            if-goto IF_TRUE0
-            goto IF_FALSE0
-            label IF_TRUE0
-            label WHILE_EXP0
-            push local 1
-            push local 0
-            eq
-            not
-            if-goto WHILE_END0
-            call Keyboard.keyPressed 0
-            pop local 0
-            goto WHILE_EXP0
-            label WHILE_END0
-            label IF_FALSE0
-            label WHILE_EXP1
-            push local 0
-            push constant 0
-            eq
-            not
-            if-goto WHILE_END1
-            call Keyboard.keyPressed 0
-            pop local 0
-            goto WHILE_EXP1
-            label WHILE_END1
-            label WHILE_EXP2
-            call Keyboard.keyPressed 0
-            push local 0
-            eq
-            not
-            if-goto WHILE_END2
-            goto WHILE_EXP2
-            label WHILE_END2
-            push local 0
-            push constant 128
-            lt
-            if-goto IF_TRUE1
-            goto IF_FALSE1
-            label IF_TRUE1
-            push local 0
-            call Output.printChar 1
-            pop temp 0
-            label IF_FALSE1
-            push local 0
-            return
-            function Keyboard.readLine 2
-            push argument 0
-            call Output.printString 1
-            pop temp 0
-            push constant 64
-            call String.new 1
-            pop local 0
-            call Keyboard.readChar 0
-            pop local 1
-            label WHILE_EXP0
-            push local 1
-            call String.newLine 0
-            eq
-            if-goto WHILE_END0
-            push local 1
-            call String.backSpace 0
-            eq
-            if-goto IF_TRUE0
-            goto IF_FALSE0
-            label IF_TRUE0
-            call Output.backSpace 0
-            pop temp 0
-            push local 0
-            call String.eraseLastChar 1
-            pop temp 0
-            label IF_FALSE0
-            push local 1
-            push constant 128
-            lt
-            if-goto IF_TRUE1
-            goto IF_FALSE1
-            label IF_TRUE1
-            push local 0
-            push local 1
-            call String.appendChar 2
-            pop temp 0
-            label IF_FALSE1
-            call Keyboard.readChar 0
-            pop local 1
-            goto WHILE_EXP0
-            label WHILE_END0
-            call Output.println 0
-            pop temp 0
-            push local 0
-            return
-            function Keyboard.readInt 3
-            push constant 0
-            pop local 2
-            push argument 0
-            call Output.printString 1
-            pop temp 0
-            push constant 64
-            call String.new 1
-            pop local 0
-            call Keyboard.readChar 0
-            pop local 1
-            label WHILE_EXP0
-            push local 1
-            push constant 45
-            eq
-            push local 2
-            push constant 0
-            eq
-            and
-            push local 1
-            push constant 47
-            gt
-            push local 1
-            push constant 58
-            lt
-            and
-            or
-            push local 1
-            push constant 129
-            eq
-            or
-            not
-            if-goto WHILE_END0
-            push local 1
-            push constant 129
-            eq
-            if-goto IF_TRUE0
-            goto IF_FALSE0
-            label IF_TRUE0
-            call Output.backSpace 0
-            pop temp 0
-            push local 0
-            call String.eraseLastChar 1
-            pop temp 0
-            push local 2
-            push constant 0
-            gt
-            if-goto IF_TRUE1
-            goto IF_FALSE1
-            label IF_TRUE1
-            push local 2
-            push constant 1
-            sub
-            pop local 2
-            label IF_FALSE1
-            goto IF_END0
-            label IF_FALSE0
-            push local 0
-            push local 1
-            call String.appendChar 2
-            pop temp 0
-            push local 2
-            push constant 1
-            add
-            pop local 2
-            label IF_END0
-            call Keyboard.readChar 0
-            pop local 1
-            goto WHILE_EXP0
-            label WHILE_END0
-            call Output.println 0
-            pop temp 0
-            push local 0
-            call String.intValue 1
-            pop local 2
-            push local 0
-            call String.dispose 1
-            pop temp 0
-            push local 2
-            return
-        ");
-
-        // their bytecode is appended to the input file
-        let linked_input = format!("{}\n{}", input, jack_library_functions);
-       
-        let code = parse_bytecode(&linked_input);
+        let code = parse_bytecode(input);
 
         // let string = format!("{:?}", code);
         // console_log!("{}", string);
@@ -313,6 +158,29 @@ impl Program {
         native_functions.insert("Sys.halt".into(), jacklib::halt);
         native_functions.insert("Sys.error".into(), jacklib::error);
 
+        // web-jack extensions, see jacklib::EXTENSIONS
+        native_functions.insert("Random.seed".into(), jacklib::random_seed);
+        native_functions.insert("Random.next".into(), jacklib::random_next);
+        native_functions.insert("Random.range".into(), jacklib::random_range);
+        native_functions.insert("Sys.time".into(), jacklib::time);
+        native_functions.insert("Sys.ticks".into(), jacklib::ticks);
+        native_functions.insert("Keyboard.isDown".into(), jacklib::is_down);
+        native_functions.insert("Keyboard.wasPressed".into(), jacklib::was_pressed);
+        native_functions.insert("Keyboard.wasReleased".into(), jacklib::was_released);
+        native_functions.insert("Mouse.x".into(), jacklib::mouse_x);
+        native_functions.insert("Mouse.y".into(), jacklib::mouse_y);
+        native_functions.insert("Mouse.buttons".into(), jacklib::mouse_buttons);
+        native_functions.insert("Mouse.clicked".into(), jacklib::mouse_clicked);
+        native_functions.insert("Gamepad.buttons".into(), jacklib::gamepad_buttons);
+        native_functions.insert("Gamepad.isDown".into(), jacklib::gamepad_is_down);
+        native_functions.insert("Gamepad.wasPressed".into(), jacklib::gamepad_was_pressed);
+        native_functions.insert("Sound.tone".into(), jacklib::tone);
+        native_functions.insert("Sound.noise".into(), jacklib::noise);
+        native_functions.insert("Sound.stop".into(), jacklib::stop_sound);
+        native_functions.insert("Storage.write".into(), jacklib::storage_write);
+        native_functions.insert("Storage.read".into(), jacklib::storage_read);
+        native_functions.insert("Storage.delete".into(), jacklib::storage_delete);
+
         let main_function = code
             .functions
             .get("Main.main")
@@ -329,15 +197,108 @@ impl Program {
             native_functions,
             memory,
             call_stack,
+            steps: 0,
+            recorder: None,
+            stack_trace: Vec::new(),
+            sleeping_until: None,
+            cycles: 0,
+            clock_rate: 0.0,
+            cycle_budget: 0.0,
+            strict: false,
+            keys: KeyQueue::new(),
+            sound: SoundBuffer::new(),
             finished: false,
         }
     }
 
+    /**
+     * Lists the functions on the call stack with the bytecode line each one is at
+     * A native function being called is listed first, since it has no frame of its own
+     */
+    fn capture_stack_trace(&self, command: &Command) -> Vec<String> {
+        let mut trace = Vec::new();
+        if let Command::Call(name, _) = command {
+            if self.native_functions.contains_key(name) {
+                trace.push(name.clone());
+            }
+        }
+        for frame in self.call_stack.iter().rev() {
+            let function = frame.function.borrow();
+            match frame
+                .next_line
+                .checked_sub(1)
+                .and_then(|line| function.commands.get(line))
+            {
+                Some(command) => trace.push(format!("{} (line {})", function.name, command.line + 1)),
+                None => trace.push(function.name.clone()),
+            }
+        }
+        trace
+    }
+
+    fn recording_frames(&self, steps_per_second: f64) -> Vec<(&[WordSize], u32)> {
+        match &self.recorder {
+            Some(recorder) => recorder.timed_frames(self.steps, steps_per_second),
+            None => Vec::new(),
+        }
+    }
+
+    fn describe_object(&self, pointer: WordSize, layout: &ClassLayout) -> String {
+        let fields: Vec<String> = layout
+            .fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                let value = match self.read_ram(pointer, i as WordSize) {
+                    Some(value) => value,
+                    None => return format!("{}: ?", field.name),
+                };
+                match field.field_type.as_deref() {
+                    Some("boolean") if value == 0 => format!("{}: false", field.name),
+                    Some("boolean") if value == -1 => format!("{}: true", field.name),
+                    Some("char") if (32..127).contains(&value) => {
+                        format!("{}: '{}'", field.name, value as u8 as char)
+                    }
+                    _ => format!("{}: {}", field.name, value),
+                }
+            })
+            .collect();
+        format!("{} @{} {{ {} }}", layout.name, pointer, fields.join(", "))
+    }
+
+    fn describe_heap_block(&self, pointer: WordSize) -> String {
+        match self.memory.heap_block(pointer) {
+            Some((block, size, _)) => {
+                let words: Vec<String> = (0..size)
+                    .map(|i| self.memory.read(block + i).unwrap_or_default().to_string())
+                    .collect();
+                format!(
+                    "heap block @{} ({} words) [{}]",
+                    block,
+                    size,
+                    words.join(", ")
+                )
+            }
+            None => format!("no heap block contains address {}", pointer),
+        }
+    }
+
+    /**
+     * Reads memory at pointer + offset, returning None outside of the memory map
+     */
+    fn read_ram(&self, pointer: WordSize, offset: WordSize) -> Option<WordSize> {
+        pointer
+            .checked_add(offset)
+            .and_then(|address| self.memory.read(address))
+    }
+
     /**
      * Execute next bytecode command.
+     * While the program is sleeping in Sys.wait nothing is executed, see sleeping_until.
+     * While it is waiting for input in a Keyboard read function, the key is fed to the read instead.
      * Returns true if display was updated, otherwise returns false.
      */
-    pub fn step(&mut self, key: WordSize) -> bool {
+    pub fn step(&mut self) -> bool {
         // if the call stack is empty, we are done
         let mut frame = match self.call_stack.last_mut() {
             Some(frame) => frame,
@@ -357,12 +318,32 @@ impl Program {
         // check for finished bit (gosh, this is pretty ugly at this point)
         if self.memory.finished {
             self.finished = true;
-            console_log!("Program completed.");
+            #[cfg(target_arch = "wasm32")]
+            wasm_bindgen_test::console_log!("Program completed.");
             return false
         }
 
         self.memory.display_updated = false;
-        self.memory.keyboard = key;
+
+        if self.sleeping_until.is_some() {
+            return false;
+        }
+
+        // a read function takes every key it is shown, so it doesn't need them held
+        let min_steps = match self.memory.key_read {
+            Some(_) => 1,
+            None => KEY_HOLD_STEPS,
+        };
+        self.memory.keyboard = self.keys.next_key(min_steps);
+
+        // a Keyboard read function returned a placeholder, which is replaced once the read is done
+        if self.memory.key_read.is_some() {
+            if let Some(value) = jacklib::read_key_helper(&mut self.memory) {
+                let sp = self.memory.get_pointer(SP);
+                self.memory.poke(sp - 1, value);
+            }
+            return self.memory.display_updated;
+        }
 
         // The current command is cloned so that the stack frame can later be mutated
         // For example during a call or return command
@@ -381,18 +362,22 @@ impl Program {
                 self.memory.push(*seg, *idx);
             }
             Command::Add => {
-                let sum = self.memory.pop(Segment::Temp, 0) + self.memory.pop(Segment::Temp, 0);
+                // the Hack ALU wraps around on overflow, as two's complement
+                let sum = self
+                    .memory
+                    .pop(Segment::Temp, 0)
+                    .wrapping_add(self.memory.pop(Segment::Temp, 0));
                 self.memory.push(Segment::Constant, sum);
             }
             Command::Sub => {
                 let first = self.memory.pop(Segment::Temp, 0);
                 let second = self.memory.pop(Segment::Temp, 0);
-                let sum = second - first;
+                let sum = second.wrapping_sub(first);
                 self.memory.push(Segment::Constant, sum);
             }
             Command::Neg => {
                 let val = self.memory.pop(Segment::Temp, 0);
-                let neg = -val;
+                let neg = val.wrapping_neg();
                 self.memory.push(Segment::Constant, neg);
             }
             Command::Eq => {
@@ -477,11 +462,35 @@ impl Program {
                         .push_stack_frame(*num_args, global_line_num as WordSize);
                     // Build and push a stack frame for the virtual call stack
                     self.call_stack.push(StackFrame::new(callee));
+                } else if self.strict && jacklib::EXTENSIONS.contains(&name.as_str()) {
+                    self.memory.raise_fault(format!(
+                        "{} is a web-jack extension, not available in strict mode",
+                        name
+                    ));
                 } else if self.native_functions.contains_key(name) {
                     // All other functions are native rust
                     let callee = self.native_functions.get(name).unwrap();
                     self.memory.push_stack_frame(*num_args, 0 as WordSize);
                     let return_value = callee(&mut self.memory, *num_args);
+                    if let Some(duration) = self.memory.wait_request.take().filter(|d| *d > 0) {
+                        self.sleeping_until = Some(self.memory.clock + duration as f64);
+                    }
+                    if name == "Memory.alloc" {
+                        // Constructors allocate their object through Memory.alloc and make it `this`
+                        // with pop pointer 0, so only such a block is tagged with the caller's class,
+                        // and only if its size matches the class layout
+                        let caller = frame.function.borrow();
+                        let class_name = caller.name.split('.').next().unwrap_or_default();
+                        let in_constructor = matches!(
+                            caller.commands.get(frame.next_line).map(|line| &line.command),
+                            Some(Command::Pop(Segment::Pointer, 0))
+                        );
+                        if let Some(layout) = self.code.classes.get(class_name) {
+                            if in_constructor && layout.fields.len() == self.memory.get_arg(0) as usize {
+                                self.memory.tag_heap_block(return_value, class_name);
+                            }
+                        }
+                    }
                     // Jack expects a return value for every function
                     self.memory.push(Segment::Constant, return_value);
                     self.memory.pop_stack_frame();
@@ -494,6 +503,30 @@ impl Program {
                 self.call_stack.pop();
             }
         }
+
+        if self.memory.fault.is_some() && self.stack_trace.is_empty() {
+            self.stack_trace = self.capture_stack_trace(&current_command.command);
+        }
+
+        let mut command_cycles = cycles::command_cycles(&current_command.command);
+        if let Command::Call(name, _) = &current_command.command {
+            if self.native_functions.contains_key(name) {
+                command_cycles += cycles::native_cycles(name);
+            }
+        }
+        self.cycles += command_cycles;
+        if self.clock_rate > 0.0 {
+            self.cycle_budget -= command_cycles as f64;
+        }
+
+        self.steps += 1;
+        if let Some(recorder) = &mut self.recorder {
+            recorder.observe(
+                self.steps,
+                self.memory.display_updated,
+                self.memory.display_words(),
+            );
+        }
         self.memory.display_updated
     }
 
@@ -533,6 +566,337 @@ impl Program {
         self.memory.display()
     }
 
+    /**
+     * wrapper for Memory.framebuffer()
+     * returns a pointer to the RGBA pixels of the display, 512 x 256 x 4 bytes
+     */
+    pub fn framebuffer(&self) -> *const u8 {
+        self.memory.framebuffer()
+    }
+
+    /**
+     * returns the length in bytes of the framebuffer
+     */
+    pub fn framebuffer_size(&self) -> usize {
+        Memory::framebuffer_size()
+    }
+
+    /**
+     * Draws the display changes since the last render onto the canvas with a single blit.
+     * Call once per animation frame rather than after every step.
+     * Returns true if anything was drawn
+     */
+    pub fn render(&mut self) -> bool {
+        self.memory.render()
+    }
+
+    /**
+     * Switches to a named palette: "green" (default), "classic", "amber" or "high-contrast".
+     * The display is redrawn immediately.
+     */
+    pub fn set_palette(&mut self, name: &str) -> Result<(), String> {
+        let palette = Palette::from_name(name).ok_or(format!("Unknown palette: {}", name))?;
+        self.memory.set_palette(palette);
+        self.memory.render();
+        Ok(())
+    }
+
+    /**
+     * Switches to a user-defined palette with fill and empty colors packed as 0xRRGGBBAA.
+     * The display is redrawn immediately.
+     */
+    pub fn set_palette_rgba(&mut self, fill: u32, empty: u32) {
+        self.memory.set_palette(Palette::from_rgba(fill, empty));
+        self.memory.render();
+    }
+
+    /**
+     * Loads glyphs for the Output library from the text font format, see CharMap::load_text.
+     * Glyphs replace the built in ones for the same codes, codes the font leaves out keep theirs.
+     * Returns the number of glyphs loaded, or nothing is changed if the font has errors
+     */
+    pub fn load_font(&mut self, source: &str) -> Result<usize, String> {
+        self.memory.char_map.load_text(source)
+    }
+
+    /**
+     * Loads glyphs for the Output library from a BDF font, see CharMap::load_bdf
+     */
+    pub fn load_bdf_font(&mut self, source: &str) -> Result<usize, String> {
+        self.memory.char_map.load_bdf(source)
+    }
+
+    /**
+     * Returns the text printed on the screen by the Output library, one line per text row
+     * Characters partly overdrawn by graphics or memory writes are left out
+     */
+    pub fn text(&self) -> String {
+        self.memory.text.text(&self.memory.char_map)
+    }
+
+    /**
+     * When enabled, text printed past the last line scrolls the screen up one line
+     * instead of wrapping the cursor back to the top
+     */
+    pub fn set_output_scrolling(&mut self, scrolling: bool) {
+        self.memory.output_scrolling = scrolling;
+    }
+
+    /**
+     * Exports the current display memory as a binary PBM image
+     */
+    pub fn screenshot_pbm(&self) -> Vec<u8> {
+        image::encode_pbm(self.memory.display_words())
+    }
+
+    /**
+     * Exports the current display memory as a PNG image, with each pixel scaled up by scale,
+     * which is kept between 1 and 16.
+     * Uses the display palette if use_palette is set, otherwise the image is black on white.
+     */
+    pub fn screenshot_png(&self, scale: u32, use_palette: bool) -> Vec<u8> {
+        let palette = use_palette.then(|| self.memory.palette());
+        let scale = scale.clamp(1, image::MAX_SCALE);
+        image::encode_png(self.memory.display_words(), scale, palette)
+    }
+
+    /**
+     * Executes up to max_steps commands, stopping early if the program finishes, sleeps
+     * or waits in a Keyboard read function for a key that hasn't been queued yet.
+     * When throttled it also stops once the cycles earned by advancing the clock are used up,
+     * so hosts can pass a generous max_steps and let the clock rate set the speed.
+     * Returns the virtual time the program sleeps until, if it is sleeping
+     */
+    pub fn run(&mut self, max_steps: u32) -> Option<f64> {
+        for _ in 0..max_steps {
+            if self.finished || self.sleeping_until.is_some() {
+                break;
+            }
+            if self.clock_rate > 0.0 && self.cycle_budget <= 0.0 {
+                break;
+            }
+            self.step();
+            if self.memory.key_read.is_some() && !self.keys.has_pending() {
+                break;
+            }
+        }
+        self.sleeping_until
+    }
+
+    /**
+     * Moves the virtual clock on by ms, waking the program once its Sys.wait is over
+     * and generating the sound samples for that time
+     * The host calls this with the real time that passed, e.g. once per animation frame,
+     * and each call counts as a tick for Sys.ticks
+     */
+    pub fn advance_clock(&mut self, ms: f64) {
+        self.memory.clock += ms;
+        self.memory.ticks += 1;
+        self.sound.generate(self.memory.voice, self.memory.clock);
+        // a sleeping program is busy waiting, so it earns no cycles to spend later
+        if self.clock_rate > 0.0 && self.sleeping_until.is_none() {
+            let max_budget = self.clock_rate * MAX_BUDGET_MS / 1000.0;
+            let earned = ms * self.clock_rate / 1000.0;
+            self.cycle_budget = f64::min(self.cycle_budget + earned, max_budget);
+        }
+        if self.sleeping_until.is_some_and(|until| until <= self.memory.clock) {
+            self.sleeping_until = None;
+        }
+    }
+
+    /**
+     * Milliseconds on the virtual clock since the program was loaded
+     */
+    pub fn clock(&self) -> f64 {
+        self.memory.clock
+    }
+
+    /**
+     * In strict mode only the standard Jack OS is available: calling one of the
+     * web-jack extensions, like Random or Sys.time, stops the program with a fault
+     */
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /**
+     * Queues a press of the key with the Hack keyboard code, see hack_key_code
+     * The key counts as down for Keyboard.isDown straight away
+     */
+    pub fn key_down(&mut self, code: WordSize) {
+        self.keys.key_down(code);
+        self.memory.keys.press(code);
+    }
+
+    pub fn key_up(&mut self, code: WordSize) {
+        self.keys.key_up(code);
+        self.memory.keys.release(code);
+    }
+
+    /**
+     * Queues the release of every key, for when the host loses track of them,
+     * e.g. keys let go while the page is in the background
+     * Keyboard.isDown sees them released straight away
+     */
+    pub fn release_keys(&mut self) {
+        self.keys.release_all();
+        self.memory.keys.release_all();
+    }
+
+    /**
+     * Queues the text as typed keys, e.g. to paste it into a Keyboard.readLine prompt
+     * Typed text only goes through the keyboard register, Keyboard.isDown doesn't see it
+     */
+    pub fn type_text(&mut self, text: &str) {
+        self.keys.type_text(text);
+    }
+
+    /**
+     * Moves the mouse pointer to a display pixel, positions off the display are moved onto its edge
+     */
+    pub fn mouse_move(&mut self, x: WordSize, y: WordSize) {
+        self.memory.mouse.move_to(x, y);
+    }
+
+    /**
+     * Sets the mouse buttons held down, as flags like the DOM MouseEvent.buttons:
+     * 1 for the main button, 2 for the secondary and 4 for the middle one
+     */
+    pub fn set_mouse_buttons(&mut self, buttons: WordSize) {
+        self.memory.mouse.set_buttons(buttons);
+    }
+
+    /**
+     * Sets the gamepad buttons held down, as flags added together:
+     * up 1, down 2, left 4, right 8, A 16, B 32, X 64, Y 128, start 256, select 512
+     * The host can call this every frame with whatever controller or keys it maps to the gamepad
+     */
+    pub fn set_gamepad(&mut self, buttons: WordSize) {
+        self.memory.gamepad.set_buttons(buttons);
+    }
+
+    /**
+     * Removes and returns the sound samples generated so far, 16 bit mono at sound_sample_rate
+     * A host playing them should call this regularly, e.g. once per animation frame
+     */
+    pub fn take_sound_samples(&mut self) -> Vec<i16> {
+        self.sound.take()
+    }
+
+    /**
+     * Removes the sound samples generated so far and returns them as a WAV file
+     */
+    pub fn take_sound_wav(&mut self) -> Vec<u8> {
+        sound::encode_wav(&self.sound.take())
+    }
+
+    pub fn sound_sample_rate(&self) -> u32 {
+        sound::SAMPLE_RATE
+    }
+
+    /**
+     * Returns everything saved with the Storage class as bytes, for the host to persist
+     */
+    pub fn export_storage(&self) -> Vec<u8> {
+        self.memory.storage.export()
+    }
+
+    /**
+     * Replaces the Storage entries with bytes from export_storage, e.g. saved in an earlier session
+     * Returns the number of entries, or nothing is changed if the bytes are malformed
+     */
+    pub fn import_storage(&mut self, bytes: &[u8]) -> Result<usize, String> {
+        self.memory.storage.import(bytes)
+    }
+
+    /**
+     * True while the program waits for input in Keyboard.readChar, readLine or readInt
+     */
+    pub fn waiting_for_key(&self) -> bool {
+        self.memory.key_read.is_some()
+    }
+
+    /**
+     * The virtual time in ms a program sleeping in Sys.wait wakes up at, if it is sleeping
+     */
+    pub fn sleeping_until(&self) -> Option<f64> {
+        self.sleeping_until
+    }
+
+    /**
+     * Emulated Hack CPU cycles used so far, estimated from the standard translation of each command
+     */
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /**
+     * Throttles run to the given Hack CPU clock rate in Hz, e.g. 1e6 for 1 MHz
+     * A rate of 0 runs unthrottled
+     */
+    pub fn set_clock_rate(&mut self, hz: f64) {
+        self.clock_rate = f64::max(hz, 0.0);
+        self.cycle_budget = 0.0;
+    }
+
+    /**
+     * Number of bytecode commands executed so far
+     */
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /**
+     * Starts recording the display, replacing any previous recording.
+     * Captures every interval steps, or whenever the display settles after an update if interval is 0.
+     */
+    pub fn start_recording(&mut self, interval: u32) {
+        self.recorder = Some(Recorder::new(
+            interval,
+            self.steps,
+            self.memory.display_words(),
+        ));
+    }
+
+    /**
+     * Stops recording. The recorded frames are kept until the next start_recording
+     */
+    pub fn stop_recording(&mut self) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.stop(self.steps, self.memory.display_words());
+        }
+    }
+
+    pub fn recording_frame_count(&self) -> usize {
+        self.recorder.as_ref().map_or(0, |r| r.frame_count())
+    }
+
+    /**
+     * Encodes the recording as an animated PNG. Frame timings are derived from the step
+     * counts between frames, played back at steps_per_second. The scale is kept between 1 and 16
+     */
+    pub fn recording_apng(&self, steps_per_second: f64, scale: u32, use_palette: bool) -> Vec<u8> {
+        let frames = self.recording_frames(steps_per_second);
+        let palette = use_palette.then(|| self.memory.palette());
+        image::encode_apng(&frames, scale.clamp(1, image::MAX_SCALE), palette)
+    }
+
+    /**
+     * Encodes the recording as an animated GIF. Frame timings are derived from the step
+     * counts between frames, played back at steps_per_second.
+     * Returns an error for a scale outside of 1 to 16
+     */
+    pub fn recording_gif(
+        &self,
+        steps_per_second: f64,
+        scale: u32,
+        use_palette: bool,
+    ) -> Result<Vec<u8>, String> {
+        let frames = self.recording_frames(steps_per_second);
+        let palette = use_palette.then(|| self.memory.palette());
+        image::encode_gif(&frames, scale, palette)
+    }
+
     /**
      * wrapper for Memory.keyboard()
      * returns the contents of the keyboard memory segment
@@ -541,7 +905,200 @@ impl Program {
         self.memory.keyboard()
     }
 
+    /**
+     * Describes the object at pointer.
+     * Objects allocated by a constructor are decoded using their class field layout,
+     * otherwise the raw words of the containing heap block are shown.
+     */
+    pub fn inspect_object(&self, pointer: WordSize) -> String {
+        match self.memory.heap_block(pointer) {
+            Some((block, _, Some(class_name))) if block == pointer => {
+                self.describe_object(pointer, &self.code.classes[class_name])
+            }
+            _ => self.describe_heap_block(pointer),
+        }
+    }
+
+    /**
+     * Describes the object at pointer as an instance of class_name.
+     * Falls back to the raw words of the containing heap block if the class has no field layout.
+     */
+    pub fn inspect_object_as(&self, pointer: WordSize, class_name: &str) -> String {
+        match self.code.classes.get(class_name) {
+            Some(layout) => self.describe_object(pointer, layout),
+            None => self.describe_heap_block(pointer),
+        }
+    }
+
+    /**
+     * Returns the message of the fault that stopped the program, if any
+     */
+    pub fn fault(&self) -> Option<String> {
+        self.memory.fault.clone()
+    }
+
+    /**
+     * Returns the Jack OS error code the program stopped with, if any
+     */
+    pub fn error_code(&self) -> Option<WordSize> {
+        self.memory.error_code
+    }
+
+    /**
+     * Returns the description of the error code the program stopped with, if any
+     */
+    pub fn error_description(&self) -> Option<String> {
+        self.memory
+            .error_code
+            .and_then(jacklib::error_description)
+            .map(String::from)
+    }
+
+    /**
+     * Returns the Jack call stack at the moment the program stopped with an error or fault,
+     * innermost call first, or an empty list if it didn't
+     */
+    pub fn stack_trace(&self) -> Vec<String> {
+        self.stack_trace.clone()
+    }
+
     pub fn end(&mut self) {
         self.memory.finished = true;
     }
 }
+
+#[cfg(test)]
+impl Program {
+    /**
+     * Runs the bytecode without a display until it finishes, for tests
+     */
+    pub(crate) fn run_headless(input: &str) -> Program {
+        let mut program = Program::with_display_target(input, DisplayTarget::Headless);
+        program.run(1_000_000);
+        assert!(program.finished, "test program didn't finish");
+        program
+    }
+
+    pub(crate) fn memory(&self) -> &Memory {
+        &self.memory
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POINT: &str = "// @class Point
+// @field int x
+// @field boolean visible
+function Point.new 0
+push constant 2
+call Memory.alloc 1
+pop pointer 0
+push argument 0
+pop this 0
+push constant 0
+not
+pop this 1
+push pointer 0
+return
+function Point.dispose 0
+push argument 0
+call Memory.deAlloc 1
+pop temp 0
+push constant 0
+return
+";
+
+    /**
+     * Runs Main.main after the Point class, returning the program and the words it left in static 0 and 1
+     */
+    fn run_main(commands: &str) -> (Program, WordSize, WordSize) {
+        let program = Program::run_headless(&format!(
+            "{}function Main.main 0\n{}\npush constant 0\nreturn\n",
+            POINT, commands
+        ));
+        assert_eq!(program.fault(), None);
+        let memory = program.memory();
+        let (first, second) = (memory.read(15).unwrap(), memory.read(16).unwrap());
+        (program, first, second)
+    }
+
+    #[test]
+    fn constructed_objects_are_shown_with_their_fields() {
+        let (program, point, _) = run_main("push constant 7\ncall Point.new 1\npop static 0");
+        assert_eq!(
+            program.inspect_object(point),
+            format!("Point @{} {{ x: 7, visible: true }}", point)
+        );
+    }
+
+    #[test]
+    fn other_blocks_are_shown_as_raw_words() {
+        let (program, array, _) = run_main("push constant 3\ncall Array.new 1\npop static 0");
+        assert_eq!(
+            program.inspect_object(array),
+            format!("heap block @{} (3 words) [0, 0, 0]", array)
+        );
+        assert_eq!(
+            program.inspect_object(array + 1),
+            program.inspect_object(array)
+        );
+        assert_eq!(
+            program.inspect_object_as(array, "Point"),
+            format!("Point @{} {{ x: 0, visible: false }}", array)
+        );
+        assert_eq!(
+            program.inspect_object_as(array, "Missing"),
+            program.inspect_object(array)
+        );
+        assert_eq!(
+            program.inspect_object(100),
+            "no heap block contains address 100"
+        );
+    }
+
+    #[test]
+    fn reused_blocks_lose_their_class() {
+        let (program, point, array) = run_main(
+            "push constant 7\ncall Point.new 1\npop static 0\n\
+             push static 0\ncall Point.dispose 1\npop temp 0\n\
+             push constant 2\ncall Array.new 1\npop static 1",
+        );
+        assert_eq!(point, array);
+        assert_eq!(
+            program.inspect_object(array),
+            format!("heap block @{} (2 words) [7, -1]", array)
+        );
+    }
+
+    #[test]
+    fn objects_without_fields_get_blocks_of_their_own() {
+        let program = Program::run_headless(
+            "// @class Game
+function Game.new 0
+push constant 0
+call Memory.alloc 1
+pop pointer 0
+push pointer 0
+return
+function Main.main 0
+call Game.new 0
+pop static 0
+call Game.new 0
+pop static 1
+push constant 0
+return
+",
+        );
+        assert_eq!(program.fault(), None);
+        assert_eq!(program.error_code(), None);
+        let memory = program.memory();
+        let (first, second) = (memory.read(15).unwrap(), memory.read(16).unwrap());
+        assert_ne!(first, second);
+        assert_eq!(
+            program.inspect_object(first),
+            format!("Game @{} {{  }}", first)
+        );
+    }
+}
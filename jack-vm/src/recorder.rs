@@ -0,0 +1,129 @@
+use crate::memory::WordSize;
+
+// Frames are held for at least a 50 fps tick, the fastest most GIF viewers play
+const MIN_FRAME_MS: u32 = 20;
+// Each frame holds a copy of display memory, 16 KB, so a recording is capped at about 16 MB
+const MAX_FRAMES: usize = 1000;
+
+/**
+ * A snapshot of display memory and the step count at which it was taken
+ */
+struct Frame {
+    display: Vec<WordSize>,
+    step: u64,
+}
+
+/**
+ * Captures display memory while a program runs, for encoding as an animation.
+ * With an interval of N the display is captured every N steps. With an interval of 0 it is
+ * captured whenever the display settles, i.e. on the first step that doesn't update the display
+ * after one or more steps that did.
+ * Consecutive identical frames are dropped, so a frame lasts until the display next changes.
+ * Once MAX_FRAMES frames are kept the recording stops by itself, as if stop was called.
+ */
+pub struct Recorder {
+    interval: u64,
+    frames: Vec<Frame>,
+    previous_display_updated: bool,
+    end_step: Option<u64>,
+}
+
+impl Recorder {
+    pub fn new(interval: u32, step: u64, display: &[WordSize]) -> Recorder {
+        let mut recorder = Recorder {
+            interval: interval as u64,
+            frames: Vec::new(),
+            previous_display_updated: false,
+            end_step: None,
+        };
+        recorder.capture(step, display);
+        recorder
+    }
+
+    /**
+     * Called after every executed step
+     */
+    pub fn observe(&mut self, step: u64, display_updated: bool, display: &[WordSize]) {
+        if self.end_step.is_some() {
+            return;
+        }
+        let capture = if self.interval == 0 {
+            self.previous_display_updated && !display_updated
+        } else {
+            step.is_multiple_of(self.interval)
+        };
+        self.previous_display_updated = display_updated;
+        if capture {
+            self.capture(step, display);
+        }
+    }
+
+    /**
+     * Stops recording, keeping the final state of the display as the last frame
+     */
+    pub fn stop(&mut self, step: u64, display: &[WordSize]) {
+        if self.end_step.is_none() {
+            self.capture(step, display);
+            self.end_step = Some(step);
+        }
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /**
+     * Returns each frame with the time in ms it is shown for, given the emulated speed.
+     * A frame lasts until the step of the next frame; the last one until the recording stopped.
+     */
+    pub fn timed_frames(
+        &self,
+        current_step: u64,
+        steps_per_second: f64,
+    ) -> Vec<(&[WordSize], u32)> {
+        let end_step = self.end_step.unwrap_or(current_step);
+        self.frames
+            .iter()
+            .enumerate()
+            .map(|(i, frame)| {
+                let next_step = self.frames.get(i + 1).map_or(end_step, |next| next.step);
+                let delay_ms = (next_step - frame.step) as f64 * 1000.0 / steps_per_second;
+                (
+                    &frame.display[..],
+                    u32::max(delay_ms.round() as u32, MIN_FRAME_MS),
+                )
+            })
+            .collect()
+    }
+
+    fn capture(&mut self, step: u64, display: &[WordSize]) {
+        match self.frames.last() {
+            Some(last) if last.display == display => (),
+            _ if self.frames.len() == MAX_FRAMES => self.end_step = Some(step),
+            _ => self.frames.push(Frame {
+                display: display.to_vec(),
+                step,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stops_once_the_frame_cap_is_reached() {
+        let mut display = vec![0; 8192];
+        let mut recorder = Recorder::new(1, 0, &display);
+        for step in 1..=(MAX_FRAMES as u64 + 10) {
+            display[0] = step as WordSize;
+            recorder.observe(step, true, &display);
+        }
+        assert_eq!(recorder.frame_count(), MAX_FRAMES);
+        let frames = recorder.timed_frames(5000, 1000.0);
+        // the last frame lasts until the step the cap was hit at
+        assert_eq!(frames.last().unwrap().1, MIN_FRAME_MS);
+        assert_eq!(recorder.end_step, Some(MAX_FRAMES as u64));
+    }
+}
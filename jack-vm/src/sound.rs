@@ -0,0 +1,232 @@
+/* This module turns the Sound class into PCM samples
+ * Some things to note:
+ *  1. Sound.tone and Sound.noise only set the voice in memory and return straight away,
+ *     samples are generated as the virtual clock advances, so sound keeps time with Sys.wait
+ *  2. samples are 16 bit mono, a square wave for tones and white noise for noise
+ *  3. the host drains the samples from a ring buffer, once it is full the oldest are dropped
+ *  4. the clock only moves when the host advances it, usually once a frame, so a sound that is
+ *     replaced or stopped before the next advance has lasted no virtual time and is never heard,
+ *     programs let a sound play with Sys.wait, which waits for the clock
+ * */
+
+use std::collections::VecDeque;
+
+use crate::memory::WordSize;
+
+pub(crate) const SAMPLE_RATE: u32 = 22050;
+// The buffer holds 10 seconds of sound, a host reading it every frame never gets near that
+const BUFFER_SAMPLES: usize = SAMPLE_RATE as usize * 10;
+// A quarter of full scale, loud enough without clipping when the host mixes it with other audio
+const AMPLITUDE: i16 = 8192;
+const NOISE_SEED: u32 = 0x1234_5678;
+
+/**
+ * What the Sound class is playing, until the virtual time in ms given
+ */
+#[derive(Clone, Copy)]
+pub enum Voice {
+    Silent,
+    Tone { frequency: WordSize, until: f64 },
+    Noise { until: f64 },
+}
+
+/**
+ * Samples generated from the voice as the virtual clock advances
+ */
+pub struct SoundBuffer {
+    samples: VecDeque<i16>,
+    // virtual time in ms of the next sample
+    sample_time: f64,
+    // position in the current square wave period, from 0 to 1
+    phase: f64,
+    noise_state: u32,
+}
+
+impl SoundBuffer {
+    pub fn new() -> SoundBuffer {
+        SoundBuffer {
+            samples: VecDeque::new(),
+            sample_time: 0.0,
+            phase: 0.0,
+            noise_state: NOISE_SEED,
+        }
+    }
+
+    /**
+     * Generates the samples of the voice up to the virtual time clock
+     */
+    pub fn generate(&mut self, voice: Voice, clock: f64) {
+        let sample_ms = 1000.0 / SAMPLE_RATE as f64;
+        while self.sample_time < clock {
+            let sample = match voice {
+                Voice::Tone { frequency, until } if self.sample_time < until => {
+                    self.phase = (self.phase + frequency as f64 / SAMPLE_RATE as f64).fract();
+                    if self.phase < 0.5 {
+                        AMPLITUDE
+                    } else {
+                        -AMPLITUDE
+                    }
+                }
+                Voice::Noise { until } if self.sample_time < until => {
+                    if self.next_noise_bit() {
+                        AMPLITUDE
+                    } else {
+                        -AMPLITUDE
+                    }
+                }
+                _ => 0,
+            };
+            if self.samples.len() == BUFFER_SAMPLES {
+                self.samples.pop_front();
+            }
+            self.samples.push_back(sample);
+            self.sample_time += sample_ms;
+        }
+    }
+
+    /**
+     * Removes and returns every sample generated so far
+     */
+    pub fn take(&mut self) -> Vec<i16> {
+        self.samples.drain(..).collect()
+    }
+
+    fn next_noise_bit(&mut self) -> bool {
+        let mut state = self.noise_state;
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        self.noise_state = state;
+        state & 1 == 1
+    }
+}
+
+/**
+ * Encodes 16 bit mono samples as a WAV file
+ */
+pub fn encode_wav(samples: &[i16]) -> Vec<u8> {
+    let data_size = (samples.len() * 2) as u32;
+    let mut wav = Vec::with_capacity(44 + data_size as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_size).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+    // fmt chunk: PCM, 1 channel, sample rate, byte rate, block align, bits per sample
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_size.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(bytes: &[u8], index: usize) -> u16 {
+        u16::from_le_bytes([bytes[index], bytes[index + 1]])
+    }
+
+    fn u32_at(bytes: &[u8], index: usize) -> u32 {
+        u32::from_le_bytes(bytes[index..index + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn wav_header_describes_the_samples() {
+        let samples = [0, 1, -1, i16::MAX, i16::MIN];
+        let wav = encode_wav(&samples);
+        assert_eq!(wav.len(), 44 + samples.len() * 2);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32_at(&wav, 4), wav.len() as u32 - 8);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&wav, 16), 16);
+        // PCM, mono, sample rate, byte rate, block align, bits per sample
+        assert_eq!(u16_at(&wav, 20), 1);
+        assert_eq!(u16_at(&wav, 22), 1);
+        assert_eq!(u32_at(&wav, 24), SAMPLE_RATE);
+        assert_eq!(u32_at(&wav, 28), SAMPLE_RATE * 2);
+        assert_eq!(u16_at(&wav, 32), 2);
+        assert_eq!(u16_at(&wav, 34), 16);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32_at(&wav, 40), samples.len() as u32 * 2);
+        let decoded: Vec<i16> = wav[44..]
+            .chunks(2)
+            .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+            .collect();
+        assert_eq!(decoded, samples);
+    }
+
+    #[test]
+    fn tones_sound_until_their_end_time() {
+        let mut sound = SoundBuffer::new();
+        let voice = Voice::Tone {
+            frequency: 440,
+            until: 100.0,
+        };
+        sound.generate(voice, 50.0);
+        sound.generate(voice, 200.0);
+        let samples = sound.take();
+        // 200 ms and 100 ms of samples, give or take one for rounding
+        assert!(
+            samples.len().abs_diff(4410) <= 1,
+            "{} samples",
+            samples.len()
+        );
+        let sounding = samples.iter().take_while(|&&sample| sample != 0).count();
+        assert!(
+            sounding.abs_diff(2205) <= 1,
+            "{} samples sounding",
+            sounding
+        );
+        assert!(samples[..sounding]
+            .iter()
+            .all(|&sample| sample == AMPLITUDE || sample == -AMPLITUDE));
+        assert!(samples[sounding..].iter().all(|&sample| sample == 0));
+        // a 440 Hz square wave changes sign 880 times a second
+        let changes = samples[..sounding]
+            .windows(2)
+            .filter(|pair| pair[0] != pair[1])
+            .count();
+        assert!(changes.abs_diff(88) <= 1, "{} sign changes", changes);
+        assert!(sound.take().is_empty());
+    }
+
+    #[test]
+    fn noise_is_random_full_scale_samples() {
+        let mut sound = SoundBuffer::new();
+        sound.generate(Voice::Noise { until: 100.0 }, 100.0);
+        let samples = sound.take();
+        let high = samples
+            .iter()
+            .filter(|&&sample| sample == AMPLITUDE)
+            .count();
+        let low = samples
+            .iter()
+            .filter(|&&sample| sample == -AMPLITUDE)
+            .count();
+        assert_eq!(high + low, samples.len());
+        assert!(high > samples.len() / 3 && low > samples.len() / 3);
+    }
+
+    #[test]
+    fn a_full_buffer_drops_the_oldest_samples() {
+        let mut sound = SoundBuffer::new();
+        // the tone is in the first of 11 seconds, which the last 10 push out of the buffer
+        let voice = Voice::Tone {
+            frequency: 440,
+            until: 900.0,
+        };
+        sound.generate(voice, 11_000.0);
+        let samples = sound.take();
+        assert_eq!(samples.len(), BUFFER_SAMPLES);
+        assert!(samples.iter().all(|&sample| sample == 0));
+    }
+}
@@ -0,0 +1,224 @@
+/* This module keeps the data Jack programs save with the Storage class
+ * Some things to note:
+ *  1. entries are arrays of words stored under a string key, kept in memory by the program
+ *  2. the host persists them by exporting the whole store as bytes and importing it next session
+ *  3. the byte format is the magic "JSTO", a version byte and the entry count as a u32, then
+ *     for each entry the key length as a u16, the key as UTF-8, the word count as a u16
+ *     and the words, all little endian
+ * */
+
+use std::collections::BTreeMap;
+
+use crate::memory::WordSize;
+
+const MAGIC: &[u8; 4] = b"JSTO";
+const VERSION: u8 = 1;
+// keys are exported with a u16 length
+const MAX_KEY_BYTES: usize = u16::MAX as usize;
+// Jack arrays are indexed with a word, so longer entries couldn't be read back
+const MAX_WORDS: usize = WordSize::MAX as usize;
+
+pub struct Storage {
+    entries: BTreeMap<String, Vec<WordSize>>,
+}
+
+impl Storage {
+    pub fn new() -> Storage {
+        Storage {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /**
+     * Saves the words under the key, as long as both fit the lengths of the export format
+     */
+    pub fn write(&mut self, key: String, words: Vec<WordSize>) -> Result<(), String> {
+        if key.len() > MAX_KEY_BYTES {
+            return Err(format!("keys are at most {} bytes", MAX_KEY_BYTES));
+        }
+        if words.len() > MAX_WORDS {
+            return Err(format!("entries are at most {} words", MAX_WORDS));
+        }
+        self.entries.insert(key, words);
+        Ok(())
+    }
+
+    pub fn read(&self, key: &str) -> Option<&[WordSize]> {
+        self.entries.get(key).map(|words| &words[..])
+    }
+
+    pub fn delete(&mut self, key: &str) {
+        self.entries.remove(key);
+    }
+
+    pub fn export(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        for (key, words) in &self.entries {
+            bytes.extend_from_slice(&(key.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(key.as_bytes());
+            bytes.extend_from_slice(&(words.len() as u16).to_le_bytes());
+            for word in words {
+                bytes.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    /**
+     * Replaces every entry with the ones in bytes written by export
+     * Returns the number of entries, or nothing is changed if the bytes are malformed
+     */
+    pub fn import(&mut self, bytes: &[u8]) -> Result<usize, String> {
+        let mut reader = ByteReader { bytes, position: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err("Not a Storage export".into());
+        }
+        let version = reader.take(1)?[0];
+        if version != VERSION {
+            return Err(format!("Unsupported Storage version {}", version));
+        }
+        let count = u32::from_le_bytes(reader.take(4)?.try_into().unwrap());
+        let mut entries = BTreeMap::new();
+        for _ in 0..count {
+            let key_length = reader.u16()? as usize;
+            let key = String::from_utf8(reader.take(key_length)?.to_vec())
+                .map_err(|_| "Storage key is not valid UTF-8".to_string())?;
+            let word_count = reader.u16()? as usize;
+            if word_count > MAX_WORDS {
+                return Err(format!("Storage entries are at most {} words", MAX_WORDS));
+            }
+            let words = reader
+                .take(word_count * 2)?
+                .chunks(2)
+                .map(|word| WordSize::from_le_bytes([word[0], word[1]]))
+                .collect();
+            entries.insert(key, words);
+        }
+        if reader.position != bytes.len() {
+            return Err("Unexpected bytes after the last Storage entry".into());
+        }
+        self.entries = entries;
+        Ok(self.entries.len())
+    }
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + length)
+            .ok_or("Storage export ends early")?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_storage() -> Storage {
+        let mut storage = Storage::new();
+        storage.write("high score".into(), vec![1200, -1]).unwrap();
+        storage.write("empty".into(), Vec::new()).unwrap();
+        storage
+            .write("→ level".into(), vec![WordSize::MIN, WordSize::MAX])
+            .unwrap();
+        storage
+    }
+
+    #[test]
+    fn exports_import_back_to_the_same_entries() {
+        let bytes = test_storage().export();
+        let mut storage = Storage::new();
+        storage.write("replaced".into(), vec![1]).unwrap();
+        assert_eq!(storage.import(&bytes), Ok(3));
+        assert_eq!(storage.read("high score"), Some(&[1200, -1][..]));
+        assert_eq!(storage.read("empty"), Some(&[][..]));
+        assert_eq!(
+            storage.read("→ level"),
+            Some(&[WordSize::MIN, WordSize::MAX][..])
+        );
+        assert_eq!(storage.read("replaced"), None);
+        assert_eq!(storage.export(), bytes);
+    }
+
+    #[test]
+    fn export_format() {
+        let mut storage = Storage::new();
+        storage.write("ab".into(), vec![1, -2]).unwrap();
+        assert_eq!(
+            storage.export(),
+            [
+                b"JSTO".as_slice(),
+                &[1, 1, 0, 0, 0, 2, 0, b'a', b'b', 2, 0, 1, 0, 0xfe, 0xff]
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn malformed_exports_are_rejected_without_changing_anything() {
+        let bytes = test_storage().export();
+        let mut bad_version = bytes.clone();
+        bad_version[4] = 2;
+        let mut bad_key = Storage::new();
+        bad_key.write("k".into(), Vec::new()).unwrap();
+        let mut bad_key = bad_key.export();
+        bad_key[11] = 0xff;
+        let mut too_long = Storage::new();
+        too_long.write("k".into(), Vec::new()).unwrap();
+        let mut too_long = too_long.export();
+        too_long.truncate(12);
+        too_long.extend_from_slice(&u16::MAX.to_le_bytes());
+        too_long.extend(std::iter::repeat_n(0, u16::MAX as usize * 2));
+        let cases: [(&[u8], &str); 7] = [
+            (b"", "Storage export ends early"),
+            (b"JSON\x01\0\0\0\0", "Not a Storage export"),
+            (&bad_version, "Unsupported Storage version 2"),
+            (&bytes[..bytes.len() - 1], "Storage export ends early"),
+            (
+                &[bytes.as_slice(), &[0]].concat(),
+                "Unexpected bytes after the last Storage entry",
+            ),
+            (&bad_key, "Storage key is not valid UTF-8"),
+            (&too_long, "Storage entries are at most 32767 words"),
+        ];
+        let mut storage = test_storage();
+        for (input, message) in cases {
+            assert_eq!(storage.import(input), Err(message.to_string()));
+        }
+        assert_eq!(storage.export(), bytes);
+    }
+
+    #[test]
+    fn keys_too_long_to_export_are_rejected() {
+        let mut storage = Storage::new();
+        let key = "é".repeat(MAX_KEY_BYTES / 2 + 1);
+        assert_eq!(
+            storage.write(key, vec![1]),
+            Err("keys are at most 65535 bytes".to_string())
+        );
+        assert_eq!(
+            storage.write("k".into(), vec![0; MAX_WORDS + 1]),
+            Err("entries are at most 32767 words".to_string())
+        );
+        assert!(storage.write("k".into(), vec![0; MAX_WORDS]).is_ok());
+        assert_eq!(
+            storage.export().len(),
+            4 + 1 + 4 + 2 + 1 + 2 + MAX_WORDS * 2
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use crate::charmap::CharMap;
+use crate::memory::{WordSize, DISPLAY_WIDTH, WORDSIZE};
+
+// The Output library lays text out in 23 lines of 64 characters, each 8 pixels wide and 11 high
+pub(crate) const LINES: WordSize = 23;
+pub(crate) const COLS: WordSize = 64;
+pub(crate) const CHAR_HEIGHT: WordSize = 11;
+
+// Marks a cell with nothing printed in it, or whose pixels were changed by something other than Output
+const EMPTY: WordSize = 0;
+
+/**
+ * The characters printed by the Output library, kept alongside the pixels they were drawn as.
+ * A cell is cleared whenever the display memory under it changes without going through Output,
+ * so the grid never claims text that is no longer on the screen.
+ */
+pub struct TextGrid {
+    cells: Vec<WordSize>,
+}
+
+impl TextGrid {
+    pub fn new() -> TextGrid {
+        TextGrid {
+            cells: vec![EMPTY; (LINES * COLS) as usize],
+        }
+    }
+
+    pub fn set(&mut self, line: WordSize, col: WordSize, character: WordSize) {
+        self.cells[(line * COLS + col) as usize] = character;
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.fill(EMPTY);
+    }
+
+    /**
+     * Moves every line up by one, leaving the last line empty
+     */
+    pub fn scroll_up(&mut self) {
+        self.cells.copy_within(COLS as usize.., 0);
+        let last_line = ((LINES - 1) * COLS) as usize;
+        self.cells[last_line..].fill(EMPTY);
+    }
+
+    /**
+     * Clears the cells covered by the changed bytes of a display word
+     * The low byte of a word holds an even column and the high byte the odd column after it
+     */
+    pub fn display_word_changed(&mut self, index: WordSize, old: WordSize, new: WordSize) {
+        let words_per_row = DISPLAY_WIDTH / WORDSIZE;
+        let line = index / words_per_row / CHAR_HEIGHT;
+        if line >= LINES {
+            return;
+        }
+        let col = index % words_per_row * 2;
+        let changed = (old ^ new) as u16;
+        if changed & 0x00ff != 0 {
+            self.set(line, col, EMPTY);
+        }
+        if changed & 0xff00 != 0 {
+            self.set(line, col + 1, EMPTY);
+        }
+    }
+
+    /**
+     * Returns the grid as lines of text, using the glyph each character was drawn with
+     * Empty cells read as spaces and trailing spaces are trimmed from every line
+     */
+    pub fn text(&self, char_map: &CharMap) -> String {
+        self.cells
+            .chunks(COLS as usize)
+            .map(|line| {
+                let text: String = line
+                    .iter()
+                    .map(|&character| match character {
+                        EMPTY => ' ',
+                        _ => char_map.glyph(character),
+                    })
+                    .collect();
+                text.trim_end().to_string()
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
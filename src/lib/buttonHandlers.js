@@ -16,6 +16,7 @@ export function onKeyUp(e) {
 
 export function onStepClick() {
     runLoop(1);
+    program.render();
     memArray = memArray; // assignment triggers reactive update in Svelte
 }
 
@@ -53,7 +54,8 @@ export function onCompileClick() {
 }
 
 export function onLoadClick() {
-    program = new Program($bytecodeStore, ctx, canvas);
+    program = new Program($bytecodeStore, canvas);
+    program.render();
     programLoaded = true;
     ramSize = program.ram_size();
     ramPointer = program.ram();
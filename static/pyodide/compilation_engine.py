@@ -168,6 +168,12 @@ class CompilationEngine:
         while self.current_token.value in ["static", "field"]:
             self.compile_class_var_dec()
 
+        #====Write class field layout====#
+        fields = [name for name in self.class_table if self.class_table.kind_of(name) == "this"]
+        fields.sort(key=self.class_table.index_of)
+        self.result += vm_writer.class_layout(
+            self.current_class, [(self.class_table.type_of(name), name) for name in fields])
+
         #=====Debugging print of class table====#
         # print("Class symbol table:")
         # print(self.class_table)
@@ -51,4 +51,14 @@ def function(name: str, n_vars: int) -> str:
 def return_statement() -> str:
         return "return\n"
 
+def class_layout(name: str, fields: list) -> str:
+    """
+    Structured comments describing the field layout of a class, in field index order.
+    The VM reads these to show objects with named fields.
+    """
+    result = f"// @class {name}\n"
+    for field_type, field_name in fields:
+        result += f"// @field {field_type} {field_name}\n"
+    return result
+
     